@@ -0,0 +1,48 @@
+use anyhow::Context;
+use anyhow::Result;
+use dithering::ColourSpace;
+use dithering::Palette;
+use dithering::animation::{Temporal, dither_frames, read_gif, write_gif};
+use std::fs::File;
+use std::io::BufWriter;
+
+fn main() -> Result<()> {
+    let palette = Palette::from(
+        [
+            [0, 0, 0],
+            [255, 255, 255],
+            [255, 255, 0],
+            [255, 0, 0],
+            [0, 0, 255],
+            [0, 255, 0],
+        ]
+        .as_slice(),
+    );
+    let mut args = std::env::args();
+    args.next(); // throw away program name
+    let input_path = args
+        .next()
+        .context("usage: animated <input_gif> <output_gif>")?;
+    let output_path = args
+        .next()
+        .context("usage: animated <input_gif> <output_gif>")?;
+
+    let mut frames = read_gif(&input_path)?;
+    println!("Read {} frames from '{}'", frames.len(), input_path);
+
+    println!("Dithering...");
+    dither_frames(
+        &mut frames,
+        &palette,
+        ColourSpace::CIELAB,
+        Temporal::KeepUnchanged { threshold: 4 },
+    );
+    println!("Done");
+
+    let file =
+        File::create(&output_path).context(format!("Failed to create file at '{output_path}'"))?;
+    write_gif(BufWriter::new(file), &frames, &palette)?;
+    println!("Wrote output to '{}'", output_path);
+
+    Ok(())
+}
@@ -1,32 +1,13 @@
 use dithering::ColourSpace;
-use dithering::Palette;
+use dithering::alpha::composite;
+use dithering::devices::INKY_IMPRESSION_7_3_SPECTRA_6;
 use dithering::quantise_and_dither_image;
 use image::GenericImageView;
+use image::Rgb;
 use image::imageops::FilterType;
 
 fn main() {
-    let palette = Palette::from_blend(
-        [
-            [0, 0, 0],
-            [255, 255, 255],
-            [255, 255, 0],
-            [255, 0, 0],
-            [0, 0, 255],
-            [0, 255, 0],
-        ]
-        .as_slice(),
-        [
-            [0, 0, 0],
-            [161, 164, 165],
-            [208, 190, 71],
-            [156, 72, 75],
-            [61, 59, 94],
-            [58, 91, 70],
-        ]
-        .as_slice(),
-        0.7,
-    )
-    .unwrap();
+    let palette = INKY_IMPRESSION_7_3_SPECTRA_6.blended_palette(0.3).unwrap();
     let mut args = std::env::args();
     args.next(); // throw away program name
     let input_path = args
@@ -39,15 +20,16 @@ fn main() {
     let img = image::open(&input_path).unwrap();
     println!(
         "Using image '{}' with dimensions: {:?}",
-        &input_path,
+        input_path,
         img.dimensions()
     );
     let img = img.resize(800, 400, FilterType::Nearest);
-    let mut buf = img.to_rgb8();
+    // the display can't show transparency, so show it as the white of the panel
+    let mut buf = composite(&img.to_rgba8(), Rgb([255, 255, 255]));
     println!("Dithering...");
     quantise_and_dither_image(&mut buf, &palette, ColourSpace::RGB);
     println!("Done");
 
     buf.save(&output_path).unwrap();
-    println!("Wrote output to '{}'", &output_path);
+    println!("Wrote output to '{}'", output_path);
 }
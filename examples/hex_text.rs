@@ -1,7 +1,8 @@
 use anyhow::Result;
 use dithering::ColourSpace;
 use dithering::Palette;
-use dithering::quantise_and_dither_image;
+use dithering::alpha::{AlphaOptions, dither_rgba};
+use dithering::diffusion::DiffusionOptions;
 use image::GenericImageView;
 use image::imageops::FilterType;
 
@@ -31,17 +32,23 @@ fn main() -> Result<()> {
     let img = image::open(&input_path).unwrap();
     println!(
         "Using image '{}' with dimensions: {:?}",
-        &input_path,
+        input_path,
         img.dimensions()
     );
     let img = img.resize(800, 400, FilterType::Nearest);
-    let mut buf = img.to_rgb8();
+    let mut buf = img.to_rgba8();
     println!("Quantising and dithering...");
-    quantise_and_dither_image(&mut buf, &palette, ColourSpace::RGB);
+    dither_rgba(
+        &mut buf,
+        &palette,
+        ColourSpace::RGB,
+        &DiffusionOptions::default(),
+        &AlphaOptions::default(),
+    );
     println!("Done");
 
     buf.save(&output_path).unwrap();
-    println!("Wrote output to '{}'", &output_path);
+    println!("Wrote output to '{}'", output_path);
 
     Ok(())
 }
@@ -2,7 +2,8 @@ use anyhow::Context;
 use anyhow::Result;
 use dithering::ColourSpace;
 use dithering::Palette;
-use dithering::quantise_and_dither_image;
+use dithering::alpha::{AlphaOptions, dither_rgba};
+use dithering::diffusion::DiffusionOptions;
 use dithering::themes::BASE16_TOKYO_NIGHT_DARK;
 use image::GenericImageView;
 
@@ -21,17 +22,23 @@ fn main() -> Result<()> {
         image::open(&input_path).context(format!("Failed to open image at '{input_path}'"))?;
     println!(
         "Using image '{}' with dimensions: {:?}",
-        &input_path,
+        input_path,
         img.dimensions()
     );
-    let mut buf = img.to_rgb8();
+    let mut buf = img.to_rgba8();
     println!("Dithering...");
-    quantise_and_dither_image(&mut buf, &palette, ColourSpace::RGB);
+    dither_rgba(
+        &mut buf,
+        &palette,
+        ColourSpace::RGB,
+        &DiffusionOptions::default(),
+        &AlphaOptions::default(),
+    );
     println!("Done");
 
     buf.save(&output_path)
         .context(format!("Failed to write image to '{output_path}'"))?;
-    println!("Wrote output to '{}'", &output_path);
+    println!("Wrote output to '{}'", output_path);
 
     Ok(())
 }
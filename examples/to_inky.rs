@@ -1,22 +1,9 @@
 use dithering::ColourSpace;
-use dithering::Palette;
-use dithering::image_to_bytes;
-use dithering::quantise_and_dither_image;
+use dithering::devices::INKY_IMPRESSION_7_3_SPECTRA_6;
 use image::GenericImageView;
-use image::imageops::FilterType;
 
 fn main() {
-    let palette = Palette::from(
-        [
-            [0, 0, 0],
-            [161, 164, 165],
-            [208, 190, 71],
-            [156, 72, 75],
-            [61, 59, 94],
-            [58, 91, 70],
-        ]
-        .as_slice(),
-    );
+    let device = INKY_IMPRESSION_7_3_SPECTRA_6;
     let mut args = std::env::args();
     args.next(); // throw away program name
     let input_path = args
@@ -26,15 +13,12 @@ fn main() {
     let img = image::open(&input_path).unwrap();
     println!(
         "Using image '{}' with dimensions: {:?}",
-        &input_path,
+        input_path,
         img.dimensions()
     );
-    let img = img.resize(800, 400, FilterType::Nearest);
-    let mut buf = img.to_rgb8();
-    println!("Quantising and dithering...");
-    quantise_and_dither_image(&mut buf, &palette, ColourSpace::RGB);
+    println!("Quantising and dithering for {}...", device.name);
+    let bytes = device.render(&img, ColourSpace::RGB, 0.0).unwrap();
     println!("Done");
 
-    let bytes = image_to_bytes(&buf, &palette);
-    println!("Got some bytes: '{:?}'", &bytes);
+    println!("Got some bytes: '{:?}'", bytes);
 }
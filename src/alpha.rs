@@ -0,0 +1,256 @@
+//! Dithering images with an alpha channel, such as sprites and icons.
+//!
+//! Alpha is reduced to fully opaque or fully transparent, then only the opaque pixels are
+//! dithered, as if the transparent ones weren't there.
+
+use crate::diffusion::{DiffusionOptions, diffuse_masked};
+use crate::direct::bayer_threshold;
+use crate::{ColourSpace, Palette, f32_to_u8};
+use anyhow::{Result, anyhow};
+use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+/// How alpha is reduced to 1-bit transparency.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Pixels with at least this alpha are opaque.
+    Threshold(u8),
+    /// Alpha is compared against a `2^order` by `2^order` Bayer matrix, so soft edges become a
+    /// regular pattern. `order` is clamped to between 1 and 8.
+    Ordered { order: u32 },
+    /// Alpha is dithered with Floyd–Steinberg error diffusion between partially transparent
+    /// pixels, so fully transparent and fully opaque pixels are kept as they are.
+    Diffused,
+}
+
+impl Default for AlphaMode {
+    fn default() -> Self {
+        Self::Threshold(128)
+    }
+}
+
+/// Options for dithering images with an alpha channel.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AlphaOptions {
+    pub mode: AlphaMode,
+    /// Partially transparent pixels that become opaque are composited over this colour first.
+    /// Otherwise their colour is used as it is.
+    pub background: Option<Rgb<u8>>,
+}
+
+/// Returns whether each pixel is opaque, in row-major order.
+#[must_use]
+pub fn alpha_mask(buf: &RgbaImage, mode: AlphaMode) -> Vec<bool> {
+    match mode {
+        AlphaMode::Threshold(threshold) => buf.pixels().map(|p| p.0[3] >= threshold).collect(),
+        AlphaMode::Ordered { order } => buf
+            .enumerate_pixels()
+            .map(|(x, y, p)| f32::from(p.0[3]) / 255.0 > bayer_threshold(x, y, order))
+            .collect(),
+        AlphaMode::Diffused => {
+            let (width, height) = buf.dimensions();
+            let mut work: Vec<[f32; 3]> = buf.pixels().map(|p| [f32::from(p.0[3]); 3]).collect();
+            let partial: Vec<bool> = buf.pixels().map(|p| !matches!(p.0[3], 0 | 255)).collect();
+            let mut mask: Vec<bool> = buf.pixels().map(|p| p.0[3] == 255).collect();
+            diffuse_masked(
+                &mut work,
+                &partial,
+                width,
+                height,
+                &DiffusionOptions::default(),
+                |x, y, alpha| {
+                    let opaque = alpha[0] >= 127.5;
+                    mask[y as usize * width as usize + x as usize] = opaque;
+                    if opaque { [255.0; 3] } else { [0.0; 3] }
+                },
+            );
+            mask
+        }
+    }
+}
+
+/// Returns the colour of a pixel composited over a background.
+fn over(pixel: Rgba<u8>, background: Rgb<u8>) -> [u8; 3] {
+    let alpha = f32::from(pixel.0[3]) / 255.0;
+    std::array::from_fn(|c| {
+        f32_to_u8(f32::from(pixel.0[c]) * alpha + f32::from(background.0[c]) * (1.0 - alpha))
+    })
+}
+
+/// Composites an image over a background colour, for outputs without transparency.
+#[must_use]
+pub fn composite(buf: &RgbaImage, background: Rgb<u8>) -> RgbImage {
+    let (width, height) = buf.dimensions();
+    RgbImage::from_fn(width, height, |x, y| {
+        Rgb(over(*buf.get_pixel(x, y), background))
+    })
+}
+
+/// Quantises the opaque pixels of an image using the given palette and colour space, diffusing
+/// the error as described by the options, after reducing alpha to 1 bit.
+///
+/// Transparent pixels are treated like pixels outside the image, so no error flows into or out
+/// of them and edges don't pick up the colour behind them. They're written with an alpha of 0
+/// and the palette's transparent colour, or black if it has none.
+pub fn dither_rgba(
+    buf: &mut RgbaImage,
+    palette: &Palette,
+    space: ColourSpace,
+    options: &DiffusionOptions,
+    alpha: &AlphaOptions,
+) {
+    let (width, height) = buf.dimensions();
+    let opaque = alpha_mask(buf, alpha.mode);
+    let error_space = options.error_space;
+    let colours = error_space.palette_coordinates(palette);
+    let mut work: Vec<[f32; 3]> = buf
+        .pixels()
+        .map(|&p| {
+            let [r, g, b, _] = p.0;
+            let colour = alpha.background.map_or([r, g, b], |bg| over(p, bg));
+            error_space.to_space(colour.map(f32::from))
+        })
+        .collect();
+
+    let [r, g, b] = palette
+        .transparent_idx()
+        .map_or([0; 3], |i| palette.get_output_colours()[usize::from(i)].0);
+    for (pixel, _) in buf.pixels_mut().zip(&opaque).filter(|&(_, &o)| !o) {
+        *pixel = Rgba([r, g, b, 0]);
+    }
+    diffuse_masked(&mut work, &opaque, width, height, options, |x, y, pixel| {
+        let idx = palette.closest_idx_f32(space, error_space.to_srgb(pixel));
+        let [r, g, b] = palette.get_output_colours()[idx].0;
+        buf.put_pixel(x, y, Rgba([r, g, b, 255]));
+        colours[idx]
+    });
+}
+
+/// Converts a quantised image into the palette index of each pixel, in row-major order, giving
+/// pixels with an alpha of 0 the palette's transparent index.
+///
+/// # Errors
+///
+/// Returns an error if the image has transparent pixels but the palette has no transparent
+/// index, see [`Palette::with_transparent`].
+pub fn rgba_to_indices(buf: &RgbaImage, palette: &Palette) -> Result<Vec<u8>> {
+    buf.pixels()
+        .map(|p| match p.0 {
+            [_, _, _, 0] => palette
+                .transparent_idx()
+                .ok_or_else(|| anyhow!("Palette has no transparent index")),
+            [r, g, b, _] => Ok(palette.to_idx(&Rgb([r, g, b]))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite() -> RgbaImage {
+        // a grey disc on a transparent red background, with a soft edge
+        RgbaImage::from_fn(24, 24, |x, y| {
+            let d = (f64::from(x) - 11.5).hypot(f64::from(y) - 11.5);
+            if d < 8.0 {
+                Rgba([100, 100, 100, 255])
+            } else if d < 10.0 {
+                Rgba([100, 100, 100, 128])
+            } else {
+                Rgba([255, 0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn modes() {
+        let buf = sprite();
+        for mode in [
+            AlphaMode::Threshold(128),
+            AlphaMode::Ordered { order: 2 },
+            AlphaMode::Diffused,
+        ] {
+            let mask = alpha_mask(&buf, mode);
+            for (p, &o) in buf.pixels().zip(&mask) {
+                match p.0[3] {
+                    0 => assert!(!o, "{mode:?}"),
+                    255 => assert!(o, "{mode:?}"),
+                    _ => {}
+                }
+            }
+            let soft: Vec<bool> = buf
+                .pixels()
+                .zip(&mask)
+                .filter(|(p, _)| p.0[3] == 128)
+                .map(|(_, &o)| o)
+                .collect();
+            let count = soft.iter().filter(|&&o| o).count();
+            if mode == AlphaMode::Threshold(128) {
+                assert_eq!(count, soft.len());
+            } else {
+                // about half of the edge
+                assert!(
+                    count * 3 > soft.len() && count * 3 < soft.len() * 2,
+                    "{mode:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_are_isolated() {
+        let palette = Palette::from([[255, 0, 255], [0, 0, 0], [255, 255, 255]].as_slice())
+            .with_transparent(0)
+            .unwrap();
+        let options = DiffusionOptions::default();
+        let mut buf = sprite();
+        dither_rgba(
+            &mut buf,
+            &palette,
+            ColourSpace::RGB,
+            &options,
+            &AlphaOptions::default(),
+        );
+        // magenta is reserved for transparency
+        for (p, &o) in buf
+            .pixels()
+            .zip(&alpha_mask(&sprite(), AlphaMode::default()))
+        {
+            if o {
+                assert!(p.0 == [0, 0, 0, 255] || p.0 == [255, 255, 255, 255]);
+            } else {
+                assert_eq!(p.0, [255, 0, 255, 0]);
+            }
+        }
+
+        // the same as dithering the opaque pixels alone, which here form the whole image
+        let mut solid = RgbaImage::from_pixel(5, 4, Rgba([100, 100, 100, 255]));
+        let mut with_border = RgbaImage::from_pixel(7, 6, Rgba([255, 0, 0, 0]));
+        image::imageops::replace(&mut with_border, &solid, 1, 1);
+        dither_rgba(
+            &mut solid,
+            &palette,
+            ColourSpace::RGB,
+            &options,
+            &AlphaOptions::default(),
+        );
+        dither_rgba(
+            &mut with_border,
+            &palette,
+            ColourSpace::RGB,
+            &options,
+            &AlphaOptions::default(),
+        );
+        let inner = image::imageops::crop_imm(&with_border, 1, 1, 5, 4).to_image();
+        assert_eq!(inner, solid);
+
+        let indices = rgba_to_indices(&with_border, &palette).unwrap();
+        assert_eq!(indices[0], 0);
+        assert_eq!(indices.iter().filter(|&&i| i == 0).count(), 7 * 6 - 5 * 4);
+        assert!(rgba_to_indices(&with_border, &Palette::monochrome()).is_err());
+
+        let flat = composite(&sprite(), Rgb([255, 255, 255]));
+        assert_eq!(flat[(0, 0)], Rgb([255, 255, 255]));
+        assert_eq!(flat[(12, 12)], Rgb([100, 100, 100]));
+        assert_eq!(flat[(11, 2)], Rgb([177, 177, 177]));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{ColourSpace, Palette, image_to_indices, quantise_and_dither_image};
+use anyhow::{Context, Result, anyhow};
+use image::codecs::gif::GifDecoder;
+use image::{AnimationDecoder, DynamicImage, RgbImage};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+/// A single frame of an animation along with how long it is displayed for.
+pub struct AnimationFrame {
+    pub image: RgbImage,
+    pub delay_ms: u32,
+}
+
+/// Controls how consecutive frames are related to each other when dithering.
+#[derive(Copy, Clone, Debug)]
+pub enum Temporal {
+    /// Every frame is dithered on its own.
+    Independent,
+    /// Pixels whose source colour hasn't moved by more than `threshold` (on any channel) since
+    /// they were last redrawn keep their previous output, which suppresses error-diffusion
+    /// flicker in static regions.
+    KeepUnchanged { threshold: u8 },
+}
+
+/// Reads every frame of an animated GIF, compositing them into full RGB frames.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be opened or decoded.
+pub fn read_gif<P: AsRef<Path>>(path: &P) -> Result<Vec<AnimationFrame>> {
+    let path = path.as_ref();
+    let file = File::open(path).context(format!("Unable to find file '{}'", path.display()))?;
+    let decoder = GifDecoder::new(BufReader::new(file))?;
+
+    decoder
+        .into_frames()
+        .map(|frame| {
+            let frame = frame?;
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            Ok(AnimationFrame {
+                image: DynamicImage::ImageRgba8(frame.into_buffer()).to_rgb8(),
+                delay_ms: numer / denom.max(1),
+            })
+        })
+        .collect()
+}
+
+/// Reads a numbered PNG sequence (e.g. `frame_0.png`, `frame_1.png`, ...) from a directory.
+///
+/// Frames are ordered by the number in their file name, and each is given the same delay.
+///
+/// # Errors
+///
+/// Returns an error if the directory can't be read, contains no PNG files, or a frame fails to
+/// decode.
+pub fn read_png_sequence<P: AsRef<Path>>(dir: &P, delay_ms: u32) -> Result<Vec<AnimationFrame>> {
+    fn frame_number(path: &Path) -> Option<u64> {
+        let stem = path.file_stem()?.to_str()?;
+        let digits: String = stem.chars().filter(char::is_ascii_digit).collect();
+        digits.parse().ok()
+    }
+
+    let dir = dir.as_ref();
+    let mut paths = std::fs::read_dir(dir)
+        .context(format!("Unable to read directory '{}'", dir.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    paths.retain(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("png")));
+    if paths.is_empty() {
+        return Err(anyhow!("No PNG frames found in '{}'", dir.display()));
+    }
+    paths.sort_by_key(|p| (frame_number(p), p.clone()));
+
+    paths
+        .iter()
+        .map(|p| {
+            let img =
+                image::open(p).context(format!("Failed to open image at '{}'", p.display()))?;
+            Ok(AnimationFrame {
+                image: img.to_rgb8(),
+                delay_ms,
+            })
+        })
+        .collect()
+}
+
+/// Quantises and dithers every frame of an animation using a shared palette.
+pub fn dither_frames(
+    frames: &mut [AnimationFrame],
+    palette: &Palette,
+    space: ColourSpace,
+    temporal: Temporal,
+) {
+    // Source colours and outputs of the pixels as they were last drawn.
+    let mut previous: Option<(RgbImage, RgbImage)> = None;
+
+    for frame in frames {
+        let source = frame.image.clone();
+        quantise_and_dither_image(&mut frame.image, palette, space);
+
+        let Temporal::KeepUnchanged { threshold } = temporal else {
+            continue;
+        };
+        match &mut previous {
+            Some((reference, output)) if reference.dimensions() == source.dimensions() => {
+                for ((src, reference), (out, prev_out)) in source
+                    .pixels()
+                    .zip(reference.pixels_mut())
+                    .zip(frame.image.pixels_mut().zip(output.pixels_mut()))
+                {
+                    let unchanged = src
+                        .0
+                        .iter()
+                        .zip(reference.0)
+                        .all(|(&a, b)| a.abs_diff(b) <= threshold);
+                    if unchanged {
+                        *out = *prev_out;
+                    } else {
+                        *reference = *src;
+                        *prev_out = *out;
+                    }
+                }
+            }
+            _ => previous = Some((source, frame.image.clone())),
+        }
+    }
+}
+
+/// Writes quantised frames as a looping animated GIF, using the palette as the global colour
+/// table.
+///
+/// Pixels that aren't palette colours are written as index 0.
+///
+/// # Errors
+///
+/// Returns an error if the palette has more than 256 colours, the frames are empty or differ in
+/// size, or writing fails.
+pub fn write_gif<W: Write>(w: W, frames: &[AnimationFrame], palette: &Palette) -> Result<()> {
+    let colours = palette.get_output_colours();
+    if colours.len() > 256 {
+        return Err(anyhow!(
+            "GIF palettes can have at most 256 colours, got: {}",
+            colours.len()
+        ));
+    }
+    let first = frames.first().context("No frames to write")?;
+    let (width, height) = first.image.dimensions();
+    let width = u16::try_from(width).context("Frame too wide for GIF")?;
+    let height = u16::try_from(height).context("Frame too tall for GIF")?;
+
+    let global_palette: Vec<u8> = colours.iter().flat_map(|c| c.0).collect();
+    let mut encoder = gif::Encoder::new(w, width, height, &global_palette)?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    for frame in frames {
+        if frame.image.dimensions() != first.image.dimensions() {
+            return Err(anyhow!(
+                "All frames should be {:?}, got: {:?}",
+                first.image.dimensions(),
+                frame.image.dimensions()
+            ));
+        }
+        let mut gif_frame = gif::Frame {
+            width,
+            height,
+            buffer: image_to_indices(&frame.image, palette).into(),
+            ..gif::Frame::default()
+        };
+        // GIF delays are in hundredths of a second
+        gif_frame.delay = u16::try_from(frame.delay_ms / 10).unwrap_or(u16::MAX);
+        encoder.write_frame(&gif_frame)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn keep_unchanged_reuses_previous_output() {
+        let palette = Palette::from([[0, 0, 0], [255, 255, 255]].as_slice());
+        // palette colours quantise to themselves, so no error reaches the changed pixel
+        let still = RgbImage::from_fn(8, 8, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        });
+        let mut moved = still.clone();
+        moved.put_pixel(7, 7, Rgb([0, 0, 0]));
+
+        let mut frames = vec![
+            AnimationFrame {
+                image: still.clone(),
+                delay_ms: 100,
+            },
+            AnimationFrame {
+                image: moved,
+                delay_ms: 100,
+            },
+        ];
+        dither_frames(
+            &mut frames,
+            &palette,
+            ColourSpace::RGB,
+            Temporal::KeepUnchanged { threshold: 0 },
+        );
+
+        for (x, y, p) in frames[1].image.enumerate_pixels() {
+            if (x, y) == (7, 7) {
+                assert_eq!(*p, Rgb([0, 0, 0]));
+            } else {
+                assert_eq!(*p, frames[0].image[(x, y)]);
+            }
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Calibration charts for measuring the colours a display actually shows.
+//!
+//! Display a chart generated by [`generate_chart`], photograph it, crop and straighten the photo
+//! to the chart's bounds, then pass it to [`fit_chart`] to get the effective palette.
+
+use crate::f32_to_u8;
+use crate::space::{linear_to_srgb, srgb_to_linear};
+use crate::{Palette, image_to_indices};
+use anyhow::{Result, anyhow};
+use image::RgbImage;
+
+/// What a chart patch shows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PatchKind {
+    /// A single palette colour.
+    Solid(u8),
+    /// A checkerboard mixing two palette colours equally.
+    Mix(u8, u8),
+}
+
+/// A square patch of a chart.
+#[derive(Copy, Clone, Debug)]
+pub struct Patch {
+    pub kind: PatchKind,
+    pub x: u32,
+    pub y: u32,
+    pub size: u32,
+}
+
+/// A generated calibration chart.
+pub struct Chart {
+    /// The chart drawn with the palette's output colours.
+    pub image: RgbImage,
+    pub patches: Vec<Patch>,
+}
+
+impl Chart {
+    /// Returns the palette index of each pixel of the chart, ready to be packed for a display,
+    /// e.g. with [`crate::devices::Device::pack`].
+    #[must_use]
+    pub fn indices(&self, palette: &Palette) -> Vec<u8> {
+        image_to_indices(&self.image, palette)
+    }
+}
+
+/// Generates a chart with a patch for every palette colour followed by a patch for every pair
+/// of colours mixed together, laid out in rows of `columns` patches.
+///
+/// Patches are separated by a gap of a quarter of their size, filled with the first palette
+/// colour.
+///
+/// # Errors
+///
+/// Returns an error if the palette is empty or has more than 256 colours, or `patch_size` or
+/// `columns` is 0.
+pub fn generate_chart(palette: &Palette, patch_size: u32, columns: u32) -> Result<Chart> {
+    let colours = palette.get_output_colours();
+    if !(1..=256).contains(&colours.len()) {
+        return Err(anyhow!("Palette should have 1 to 256 colours"));
+    }
+    let last = u8::try_from(colours.len() - 1)?;
+    if patch_size == 0 || columns == 0 {
+        return Err(anyhow!("Patch size and columns should be greater than 0"));
+    }
+
+    let mut kinds: Vec<PatchKind> = (0..=last).map(PatchKind::Solid).collect();
+    for a in 0..=last {
+        for b in (a..=last).skip(1) {
+            kinds.push(PatchKind::Mix(a, b));
+        }
+    }
+
+    let gap = (patch_size / 4).max(1);
+    let pitch = patch_size + gap;
+    let count = u32::try_from(kinds.len())?;
+    let rows = count.div_ceil(columns);
+    let mut image = RgbImage::from_pixel(
+        gap + columns.min(count) * pitch,
+        gap + rows * pitch,
+        colours[0],
+    );
+
+    let mut patches = Vec::with_capacity(kinds.len());
+    for (i, kind) in (0..).zip(kinds) {
+        let patch = Patch {
+            kind,
+            x: gap + (i % columns) * pitch,
+            y: gap + (i / columns) * pitch,
+            size: patch_size,
+        };
+        for y in patch.y..patch.y + patch_size {
+            for x in patch.x..patch.x + patch_size {
+                let idx = match kind {
+                    PatchKind::Solid(a) => a,
+                    PatchKind::Mix(a, b) => {
+                        if (x + y) % 2 == 0 {
+                            a
+                        } else {
+                            b
+                        }
+                    }
+                };
+                image.put_pixel(x, y, colours[usize::from(idx)]);
+            }
+        }
+        patches.push(patch);
+    }
+
+    Ok(Chart { image, patches })
+}
+
+/// The result of fitting a photographed chart.
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    /// Measured colour of each palette entry.
+    pub colours: Vec<[u8; 3]>,
+    /// For each mixed pair of palette indices, where the measured mix lies on the line from the
+    /// first colour to the second, in linear light. 0.5 means the mix behaves linearly, and
+    /// larger values mean the second colour dominates.
+    pub mix_weights: Vec<(u8, u8, f32)>,
+}
+
+impl Calibration {
+    /// Returns the measured palette.
+    #[must_use]
+    pub fn palette(&self) -> Palette {
+        self.colours.as_slice().into()
+    }
+
+    /// Returns a palette that matches against the measured colours but outputs the colours the
+    /// chart was generated with, see [`Palette::paired`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `drive` has a different number of colours to the calibration.
+    pub fn paired_palette(&self, drive: &Palette) -> Result<Palette> {
+        let drive: Vec<[u8; 3]> = drive.get_output_colours().iter().map(|c| c.0).collect();
+        Palette::paired(&self.colours, &drive)
+    }
+
+    /// Returns the mean of the mix weights, summarising how nonlinear the display's mixing is.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn mean_mix_weight(&self) -> Option<f32> {
+        if self.mix_weights.is_empty() {
+            return None;
+        }
+        let sum: f32 = self.mix_weights.iter().map(|&(_, _, w)| w).sum();
+        Some(sum / self.mix_weights.len() as f32)
+    }
+}
+
+/// Averages the centre of a patch in linear light, scaling the patch from chart coordinates to
+/// photo coordinates.
+#[allow(clippy::cast_precision_loss)]
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn average_patch(photo: &RgbImage, patch: &Patch, scale: (f32, f32)) -> Result<[f32; 3]> {
+    // ignore the outer quarter on each side, which is blurred by the camera and misalignment
+    let inset = patch.size as f32 / 4.0;
+    let x0 = ((patch.x as f32 + inset) * scale.0) as u32;
+    let y0 = ((patch.y as f32 + inset) * scale.1) as u32;
+    let x1 = (((patch.x + patch.size) as f32 - inset) * scale.0).ceil() as u32;
+    let y1 = (((patch.y + patch.size) as f32 - inset) * scale.1).ceil() as u32;
+    let x1 = x1.min(photo.width()).max(x0 + 1);
+    let y1 = y1.min(photo.height()).max(y0 + 1);
+    if x0 >= photo.width() || y0 >= photo.height() {
+        return Err(anyhow!(
+            "Patch at ({}, {}) is outside the photo",
+            patch.x,
+            patch.y
+        ));
+    }
+
+    let mut sum = [0.0; 3];
+    for y in y0..y1 {
+        for x in x0..x1 {
+            for (s, &c) in sum.iter_mut().zip(&photo.get_pixel(x, y).0) {
+                *s += srgb_to_linear(f32::from(c) / 255.0);
+            }
+        }
+    }
+    let count = ((x1 - x0) * (y1 - y0)) as f32;
+    Ok(sum.map(|s| s / count))
+}
+
+/// Derives the colours a display shows from a photo of a chart.
+///
+/// The photo should be cropped and straightened to the chart's bounds; it may be a different
+/// size to the chart.
+///
+/// # Errors
+///
+/// Returns an error if a patch lies outside the photo or the chart has no solid patches.
+#[allow(clippy::cast_precision_loss)]
+pub fn fit_chart(photo: &RgbImage, chart: &Chart) -> Result<Calibration> {
+    let scale = (
+        photo.width() as f32 / chart.image.width() as f32,
+        photo.height() as f32 / chart.image.height() as f32,
+    );
+
+    let mut solids: Vec<(u8, [f32; 3])> = Vec::new();
+    let mut mixes: Vec<(u8, u8, [f32; 3])> = Vec::new();
+    for patch in &chart.patches {
+        let avg = average_patch(photo, patch, scale)?;
+        match patch.kind {
+            PatchKind::Solid(a) => solids.push((a, avg)),
+            PatchKind::Mix(a, b) => mixes.push((a, b, avg)),
+        }
+    }
+    if solids.is_empty() {
+        return Err(anyhow!("Chart has no solid patches"));
+    }
+    solids.sort_by_key(|&(a, _)| a);
+
+    let linear = |idx: u8| solids.iter().find(|&&(a, _)| a == idx).map(|&(_, c)| c);
+    let mix_weights = mixes
+        .iter()
+        .filter_map(|&(a, b, mix)| {
+            let (ca, cb) = (linear(a)?, linear(b)?);
+            let dir: [f32; 3] = std::array::from_fn(|i| cb[i] - ca[i]);
+            let len_sq: f32 = dir.iter().map(|d| d * d).sum();
+            // colours that look the same tell us nothing about mixing
+            if len_sq < 1e-4 {
+                return None;
+            }
+            let t: f32 = (0..3).map(|i| (mix[i] - ca[i]) * dir[i]).sum::<f32>() / len_sq;
+            Some((a, b, t))
+        })
+        .collect();
+
+    Ok(Calibration {
+        colours: solids
+            .iter()
+            .map(|(_, c)| c.map(|v| f32_to_u8(linear_to_srgb(v) * 255.0)))
+            .collect(),
+        mix_weights,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    /// Draws a chart as a display with the given measured colours would show it, for testing.
+    fn simulate(chart: &Chart, palette: &Palette, measured: &[[u8; 3]]) -> RgbImage {
+        let indices = chart.indices(palette);
+        RgbImage::from_fn(chart.image.width(), chart.image.height(), |x, y| {
+            Rgb(measured[usize::from(indices[(y * chart.image.width() + x) as usize])])
+        })
+    }
+
+    #[test]
+    fn fits_simulated_display() {
+        let palette = Palette::from([[0, 0, 0], [255, 255, 255], [255, 0, 0]].as_slice());
+        let measured = [[20, 20, 20], [200, 200, 190], [150, 40, 40]];
+        let chart = generate_chart(&palette, 16, 4).unwrap();
+        assert_eq!(chart.patches.len(), 6);
+
+        let photo = simulate(&chart, &palette, &measured);
+        let calibration = fit_chart(&photo, &chart).unwrap();
+        assert_eq!(calibration.colours, measured);
+        for &(_, _, w) in &calibration.mix_weights {
+            assert!((w - 0.5).abs() < 0.01, "{w}");
+        }
+    }
+
+    #[test]
+    fn palette_sizes() {
+        let grey = |len: u32| -> Palette {
+            let colours: Vec<[u8; 3]> = (0..len)
+                .map(|i| [u8::try_from(i % 256).unwrap(); 3])
+                .collect();
+            colours.as_slice().into()
+        };
+        let chart = generate_chart(&grey(256), 1, 256).unwrap();
+        assert_eq!(chart.patches.len(), 256 + 256 * 255 / 2);
+        assert_eq!(chart.patches[255].kind, PatchKind::Solid(255));
+        assert_eq!(chart.patches.last().unwrap().kind, PatchKind::Mix(254, 255));
+        assert!(generate_chart(&grey(257), 1, 256).is_err());
+        assert!(generate_chart(&grey(0), 1, 256).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+//! Direct binary search halftoning.
+//!
+//! <https://doi.org/10.1117/12.19684>
+//!
+//! Starting from an existing dithered image, pixels are repeatedly changed to another palette
+//! colour, or swapped with a neighbour, whenever that brings the image closer to the source as
+//! seen through a model of the human visual system. It is much slower than error diffusion, but
+//! gives the cleanest results.
+
+#[cfg(feature = "std")]
+use crate::DitherPixel;
+use crate::diffusion::ErrorSpace;
+use crate::float::{ceil, exp};
+#[cfg(feature = "std")]
+use crate::pixel::{row_major, write_indices};
+use crate::{Palette, Rgb};
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{Result, anyhow};
+#[cfg(feature = "std")]
+use image::{GenericImage, GenericImageView};
+
+/// Options for direct binary search.
+#[derive(Copy, Clone, Debug)]
+pub struct DbsOptions {
+    /// Most passes made over the image. Searching stops early once a pass changes nothing.
+    pub iterations: u32,
+    /// Standard deviation of the Gaussian filter modelling the human visual system, in pixels.
+    /// Wider filters suit images viewed from further away, and take longer.
+    pub filter_width: f32,
+    pub error_space: ErrorSpace,
+}
+
+impl Default for DbsOptions {
+    fn default() -> Self {
+        Self {
+            iterations: 10,
+            filter_width: 1.2,
+            error_space: ErrorSpace::default(),
+        }
+    }
+}
+
+/// The neighbours a pixel may be swapped with.
+const NEIGHBOURS: [(i64, i64); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// A change to the halftone that lowers the visible error.
+struct Move {
+    /// Change in the visible error.
+    change: f32,
+    /// New palette index of the pixel.
+    idx: usize,
+    /// Position of the neighbour swapped with, if any.
+    swap: Option<(i64, i64)>,
+}
+
+/// The autocorrelation of the visual system filter, which is all the search needs.
+struct Autocorrelation {
+    radius: i64,
+    values: Vec<f32>,
+}
+
+impl Autocorrelation {
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_precision_loss)]
+    fn gaussian(sigma: f32) -> Self {
+        let sigma = sigma.max(0.1);
+        let r = ceil(3.0 * sigma) as i64;
+        let size = 2 * r + 1;
+        let mut filter: Vec<f32> = (-r..=r)
+            .flat_map(|y| (-r..=r).map(move |x| (x, y)))
+            .map(|(x, y)| exp(-((x * x + y * y) as f32) / (2.0 * sigma * sigma)))
+            .collect();
+        let sum: f32 = filter.iter().sum();
+        filter.iter_mut().for_each(|f| *f /= sum);
+
+        let radius = 2 * r;
+        let values = (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .map(|(dx, dy)| {
+                let mut total = 0.0;
+                for y in (-r).max(-r - dy)..=r.min(r - dy) {
+                    for x in (-r).max(-r - dx)..=r.min(r - dx) {
+                        let a = filter[((y + r) * size + x + r) as usize];
+                        let b = filter[((y + dy + r) * size + x + dx + r) as usize];
+                        total += a * b;
+                    }
+                }
+                total
+            })
+            .collect();
+        Self { radius, values }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn get(&self, dx: i64, dy: i64) -> f32 {
+        if dx.abs() > self.radius || dy.abs() > self.radius {
+            return 0.0;
+        }
+        let size = 2 * self.radius + 1;
+        self.values[((dy + self.radius) * size + dx + self.radius) as usize]
+    }
+}
+
+/// Refines a dithered image `buf` of `source`, which must be quantised to `palette`, in place.
+///
+/// Pixels of the palette's transparent colour are left as they are, and no other pixel is changed
+/// to it.
+///
+/// Returns the number of passes made.
+///
+/// # Errors
+///
+/// Returns an error if `buf` and `source` are different sizes, or the palette is empty.
+#[cfg(feature = "std")]
+pub fn direct_binary_search<I, S>(
+    buf: &mut I,
+    source: &S,
+    palette: &Palette,
+    options: &DbsOptions,
+) -> Result<u32>
+where
+    I: GenericImage,
+    I::Pixel: DitherPixel,
+    S: GenericImageView,
+    S::Pixel: DitherPixel,
+{
+    if buf.dimensions() != source.dimensions() {
+        return Err(anyhow!(
+            "Dithered image is {:?} but source is {:?}",
+            buf.dimensions(),
+            source.dimensions()
+        ));
+    }
+    if palette.get_colours().is_empty() {
+        return Err(anyhow!("Palette should have at least 1 colour"));
+    }
+    let (width, height) = buf.dimensions();
+    let transparent = transparent_colour(palette);
+    let mut indices: Vec<usize> = row_major(buf)
+        .map(|p| match transparent {
+            Some((t, c)) if p.to_srgb() == I::Pixel::from_palette(c).to_srgb() => t,
+            _ => usize::from(p.to_idx(palette)),
+        })
+        .collect();
+    let source: Vec<[f32; 3]> = row_major(source).map(|p| p.to_srgb()).collect();
+    let passes = search(&mut indices, &source, width, height, palette, options);
+    write_indices(buf, indices, palette);
+    Ok(passes)
+}
+
+/// Like [`direct_binary_search`], for `width` by `height` images stored as row-major pixels.
+///
+/// # Errors
+///
+/// Returns an error if `pixels` or `source` aren't `width` by `height`, or the palette is empty.
+pub fn direct_binary_search_pixels(
+    pixels: &mut [[u8; 3]],
+    source: &[[u8; 3]],
+    width: u32,
+    height: u32,
+    palette: &Palette,
+    options: &DbsOptions,
+) -> Result<u32> {
+    let len = width as usize * height as usize;
+    if pixels.len() != len || source.len() != len {
+        return Err(anyhow!(
+            "Expected {len} pixels for {width}x{height}, got: {} dithered and {} source",
+            pixels.len(),
+            source.len()
+        ));
+    }
+    if palette.get_colours().is_empty() {
+        return Err(anyhow!("Palette should have at least 1 colour"));
+    }
+
+    let transparent = transparent_colour(palette);
+    let mut indices: Vec<usize> = pixels
+        .iter()
+        .map(|&p| match transparent {
+            Some((t, c)) if c.0 == p => t,
+            _ => usize::from(palette.to_idx(&Rgb(p))),
+        })
+        .collect();
+    let source: Vec<[f32; 3]> = source.iter().map(|p| p.map(f32::from)).collect();
+    let passes = search(&mut indices, &source, width, height, palette, options);
+    for (pixel, idx) in pixels.iter_mut().zip(indices) {
+        *pixel = palette.get_output_colours()[idx].0;
+    }
+    Ok(passes)
+}
+
+/// Returns the transparent index of a palette and its colour, if it has one.
+///
+/// [`Palette::to_idx`] never returns the transparent index, so pixels of this colour are matched
+/// separately to keep them transparent.
+fn transparent_colour(palette: &Palette) -> Option<(usize, Rgb<u8>)> {
+    let idx = usize::from(palette.transparent_idx()?);
+    Some((idx, palette.get_output_colours()[idx]))
+}
+
+/// Refines the palette `indices` of a halftone of row-major sRGB `source` pixels in place,
+/// returning the number of passes made.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn search(
+    indices: &mut [usize],
+    source: &[[f32; 3]],
+    width: u32,
+    height: u32,
+    palette: &Palette,
+    options: &DbsOptions,
+) -> u32 {
+    let (w, h) = (i64::from(width), i64::from(height));
+    let error_space = options.error_space;
+    let colours = error_space.palette_coordinates(palette);
+    let transparent = palette.transparent_idx().map(usize::from);
+    let error: Vec<[f32; 3]> = source
+        .iter()
+        .zip(indices.iter())
+        .map(|(&p, &idx)| {
+            let p = error_space.to_space(p);
+            core::array::from_fn(|c| colours[idx][c] - p[c])
+        })
+        .collect();
+
+    let cpp = Autocorrelation::gaussian(options.filter_width);
+    let r = cpp.radius;
+    let around = |x: i64, y: i64| {
+        ((y - r).max(0)..(y + r + 1).min(h))
+            .flat_map(move |ny| ((x - r).max(0)..(x + r + 1).min(w)).map(move |nx| (nx, ny)))
+    };
+
+    // the error filtered by the autocorrelation, which gives the change in the visible error
+    // when a pixel changes
+    let mut cep: Vec<[f32; 3]> = (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let mut total = [0.0; 3];
+            for (nx, ny) in around(x, y) {
+                let weight = cpp.get(nx - x, ny - y);
+                for (t, e) in total.iter_mut().zip(error[(ny * w + nx) as usize]) {
+                    *t += weight * e;
+                }
+            }
+            total
+        })
+        .collect();
+    let centre = cpp.get(0, 0);
+    let dot = |a: [f32; 3], b: [f32; 3]| a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let delta = |from: usize, to: usize| -> [f32; 3] {
+        core::array::from_fn(|c| colours[to][c] - colours[from][c])
+    };
+
+    let mut passes = 0;
+    while passes < options.iterations {
+        passes += 1;
+        let mut changed = false;
+
+        for y in 0..h {
+            for x in 0..w {
+                let m = (y * w + x) as usize;
+                let current = indices[m];
+                if Some(current) == transparent {
+                    continue;
+                }
+
+                let mut best: Option<Move> = None;
+                let candidates = colours
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| i != current && Some(i) != transparent);
+                for (idx, _) in candidates {
+                    let a = delta(current, idx);
+                    let change = 2.0 * dot(a, cep[m]) + centre * dot(a, a);
+                    if change < best.as_ref().map_or(0.0, |b| b.change) {
+                        best = Some(Move {
+                            change,
+                            idx,
+                            swap: None,
+                        });
+                    }
+                }
+                for (dx, dy) in NEIGHBOURS {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if !(0..w).contains(&nx) || !(0..h).contains(&ny) {
+                        continue;
+                    }
+                    let n = (ny * w + nx) as usize;
+                    if indices[n] == current || Some(indices[n]) == transparent {
+                        continue;
+                    }
+                    let a = delta(current, indices[n]);
+                    let change = 2.0 * dot(a, cep[m]) - 2.0 * dot(a, cep[n])
+                        + 2.0 * centre * dot(a, a)
+                        - 2.0 * cpp.get(dx, dy) * dot(a, a);
+                    if change < best.as_ref().map_or(0.0, |b| b.change) {
+                        best = Some(Move {
+                            change,
+                            idx: indices[n],
+                            swap: Some((nx, ny)),
+                        });
+                    }
+                }
+
+                let Some(Move { idx, swap, .. }) = best else {
+                    continue;
+                };
+                let mut updates = vec![(x, y, delta(current, idx))];
+                if let Some((nx, ny)) = swap {
+                    let n = (ny * w + nx) as usize;
+                    updates.push((nx, ny, delta(indices[n], current)));
+                    indices[n] = current;
+                }
+                indices[m] = idx;
+                for (ux, uy, a) in updates {
+                    for (nx, ny) in around(ux, uy) {
+                        let weight = cpp.get(nx - ux, ny - uy);
+                        for (t, d) in cep[(ny * w + nx) as usize].iter_mut().zip(a) {
+                            *t += weight * d;
+                        }
+                    }
+                }
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+    passes
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{ColourSpace, quantise_and_dither_image, quantise_image};
+    use image::{Rgb, RgbImage};
+
+    /// Visible error of a halftone, blurred the same way the search sees it.
+    fn visible_error(buf: &RgbImage, source: &RgbImage) -> f32 {
+        let blur = |img: &RgbImage| image::imageops::blur(img, 1.2);
+        blur(buf)
+            .pixels()
+            .zip(blur(source).pixels())
+            .map(|(a, b)| (f32::from(a.0[0]) - f32::from(b.0[0])).powi(2))
+            .sum()
+    }
+
+    #[test]
+    fn improves_on_starting_point() {
+        let source = RgbImage::from_fn(24, 24, |x, _| {
+            let v = u8::try_from(x * 10).unwrap();
+            Rgb([v, v, v])
+        });
+        let palette = Palette::monochrome();
+        for start in [quantise_image, quantise_and_dither_image] {
+            let mut buf = source.clone();
+            start(&mut buf, &palette, ColourSpace::RGB);
+            let before = visible_error(&buf, &source);
+            let passes =
+                direct_binary_search(&mut buf, &source, &palette, &DbsOptions::default()).unwrap();
+            assert!(passes > 0);
+            assert!(visible_error(&buf, &source) < before);
+            assert!(buf.pixels().all(|p| p.0 == [0; 3] || p.0 == [255; 3]));
+        }
+    }
+
+    #[test]
+    fn skips_transparent_colour() {
+        // a non-zero index, as unmatched colours also read back as 0
+        let palette = Palette::from([[0, 0, 0], [255, 255, 255], [128, 128, 128]].as_slice())
+            .with_transparent(2)
+            .unwrap();
+        let source = RgbImage::from_pixel(16, 16, Rgb([128; 3]));
+        let mut buf = source.clone();
+        quantise_and_dither_image(&mut buf, &palette, ColourSpace::RGB);
+        buf.put_pixel(3, 3, Rgb([128; 3]));
+        direct_binary_search(&mut buf, &source, &palette, &DbsOptions::default()).unwrap();
+        for (x, y, p) in buf.enumerate_pixels() {
+            if (x, y) == (3, 3) {
+                assert_eq!(p.0, [128; 3]);
+            } else {
+                assert!(p.0 == [0; 3] || p.0 == [255; 3], "{x},{y}: {p:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! Profiles for common e-paper panels.
+//!
+//! Each profile bundles the panel's nominal palette, the colours it actually displays, its
+//! native resolution and how its controller expects pixels to be packed. The measured palettes
+//! are typical values and vary between panels, so calibrate your own for the best results.
+
+use crate::Palette;
+use crate::formats::planes::{PlaneLayout, TRI_COLOUR, indices_to_planes};
+use crate::orientation::Orientation;
+#[cfg(feature = "std")]
+use crate::{ColourSpace, image_to_indices, quantise_and_dither_image};
+use alloc::vec::Vec;
+use anyhow::{Result, anyhow};
+#[cfg(feature = "std")]
+use image::DynamicImage;
+#[cfg(feature = "std")]
+use image::imageops::FilterType;
+
+/// How palette indices are packed into the bytes sent to a controller.
+#[derive(Copy, Clone, Debug)]
+pub enum Packing {
+    /// Two pixels per byte, left pixel in the high nibble. Palette index `i` is sent as
+    /// `codes[i]`.
+    Nibbles(&'static [u8]),
+    /// Four pixels per byte, leftmost pixel in the most significant bits. Palette index `i` is
+    /// sent as `codes[i]`.
+    TwoBit(&'static [u8]),
+    /// Separate 1-bit planes, one after the other.
+    Planes(PlaneLayout<'static>),
+}
+
+/// An e-paper panel profile.
+///
+/// `width` and `height` are the native resolution in the controller's scan order.
+#[derive(Copy, Clone, Debug)]
+pub struct Device {
+    pub name: &'static str,
+    pub width: u32,
+    pub height: u32,
+    /// Colours the controller is driven with.
+    pub palette: &'static [[u8; 3]],
+    /// Colours the panel actually shows for each entry of `palette`.
+    pub measured_palette: &'static [[u8; 3]],
+    pub packing: Packing,
+    /// Transform from the logical orientation images are dithered in to the order the
+    /// controller scans in, for panels mounted rotated or mirrored.
+    pub orientation: Orientation,
+}
+
+const ACEP_7_PALETTE: [[u8; 3]; 7] = [
+    [0, 0, 0],
+    [255, 255, 255],
+    [0, 255, 0],
+    [0, 0, 255],
+    [255, 0, 0],
+    [255, 255, 0],
+    [255, 128, 0],
+];
+const ACEP_7_MEASURED: [[u8; 3]; 7] = [
+    [57, 48, 57],
+    [255, 255, 255],
+    [58, 91, 70],
+    [61, 59, 94],
+    [156, 72, 75],
+    [208, 190, 71],
+    [177, 106, 73],
+];
+const ACEP_7_CODES: [u8; 7] = [0, 1, 2, 3, 4, 5, 6];
+
+const SPECTRA_6_PALETTE: [[u8; 3]; 6] = [
+    [0, 0, 0],
+    [255, 255, 255],
+    [255, 255, 0],
+    [255, 0, 0],
+    [0, 0, 255],
+    [0, 255, 0],
+];
+const SPECTRA_6_MEASURED: [[u8; 3]; 6] = [
+    [0, 0, 0],
+    [161, 164, 165],
+    [208, 190, 71],
+    [156, 72, 75],
+    [61, 59, 94],
+    [58, 91, 70],
+];
+// Spectra 6 controllers skip code 4
+const SPECTRA_6_CODES: [u8; 6] = [0, 1, 2, 3, 5, 6];
+
+/// Pimoroni Inky Impression 4" (7 colour ACeP).
+pub const INKY_IMPRESSION_4: Device = Device {
+    name: "Inky Impression 4\"",
+    width: 640,
+    height: 400,
+    palette: &ACEP_7_PALETTE,
+    measured_palette: &ACEP_7_MEASURED,
+    packing: Packing::Nibbles(&ACEP_7_CODES),
+    orientation: Orientation::IDENTITY,
+};
+
+/// Pimoroni Inky Impression 5.7" (7 colour ACeP).
+pub const INKY_IMPRESSION_5_7: Device = Device {
+    name: "Inky Impression 5.7\"",
+    width: 600,
+    height: 448,
+    palette: &ACEP_7_PALETTE,
+    measured_palette: &ACEP_7_MEASURED,
+    packing: Packing::Nibbles(&ACEP_7_CODES),
+    orientation: Orientation::IDENTITY,
+};
+
+/// Pimoroni Inky Impression 7.3" (7 colour ACeP).
+pub const INKY_IMPRESSION_7_3: Device = Device {
+    name: "Inky Impression 7.3\"",
+    width: 800,
+    height: 480,
+    palette: &ACEP_7_PALETTE,
+    measured_palette: &ACEP_7_MEASURED,
+    packing: Packing::Nibbles(&ACEP_7_CODES),
+    orientation: Orientation::IDENTITY,
+};
+
+/// Pimoroni Inky Impression 7.3" (Spectra 6).
+pub const INKY_IMPRESSION_7_3_SPECTRA_6: Device = Device {
+    name: "Inky Impression 7.3\" Spectra 6",
+    width: 800,
+    height: 480,
+    palette: &SPECTRA_6_PALETTE,
+    measured_palette: &SPECTRA_6_MEASURED,
+    packing: Packing::Nibbles(&SPECTRA_6_CODES),
+    orientation: Orientation::IDENTITY,
+};
+
+/// Waveshare 7.3" ACeP 7 colour (F).
+pub const WAVESHARE_7_3_ACEP: Device = Device {
+    name: "Waveshare 7.3\" ACeP",
+    width: 800,
+    height: 480,
+    palette: &ACEP_7_PALETTE,
+    measured_palette: &ACEP_7_MEASURED,
+    packing: Packing::Nibbles(&ACEP_7_CODES),
+    orientation: Orientation::IDENTITY,
+};
+
+/// Waveshare 7.3" Spectra 6 (E).
+pub const WAVESHARE_7_3_SPECTRA_6: Device = Device {
+    name: "Waveshare 7.3\" Spectra 6",
+    width: 800,
+    height: 480,
+    palette: &SPECTRA_6_PALETTE,
+    measured_palette: &SPECTRA_6_MEASURED,
+    packing: Packing::Nibbles(&SPECTRA_6_CODES),
+    orientation: Orientation::IDENTITY,
+};
+
+/// Black, white and red panel, e.g. Pimoroni Inky wHAT.
+pub const TRI_COLOUR_RED: Device = Device {
+    name: "Black/white/red",
+    width: 400,
+    height: 300,
+    palette: &[[0, 0, 0], [255, 255, 255], [255, 0, 0]],
+    measured_palette: &[[26, 26, 26], [225, 225, 220], [165, 40, 40]],
+    packing: Packing::Planes(TRI_COLOUR),
+    orientation: Orientation::IDENTITY,
+};
+
+/// Black, white and yellow panel, e.g. Pimoroni Inky wHAT.
+pub const TRI_COLOUR_YELLOW: Device = Device {
+    name: "Black/white/yellow",
+    width: 400,
+    height: 300,
+    palette: &[[0, 0, 0], [255, 255, 255], [255, 255, 0]],
+    measured_palette: &[[26, 26, 26], [225, 225, 220], [215, 190, 45]],
+    packing: Packing::Planes(TRI_COLOUR),
+    orientation: Orientation::IDENTITY,
+};
+
+/// 4 level greyscale panel, e.g. Waveshare 4.2".
+pub const GREYSCALE_4: Device = Device {
+    name: "4 level greyscale",
+    width: 400,
+    height: 300,
+    palette: &[[0, 0, 0], [85, 85, 85], [170, 170, 170], [255, 255, 255]],
+    measured_palette: &[[30, 30, 30], [95, 95, 95], [160, 160, 160], [225, 225, 225]],
+    packing: Packing::TwoBit(&[0, 1, 2, 3]),
+    orientation: Orientation::IDENTITY,
+};
+
+/// Every built in device profile.
+pub const DEVICES: [Device; 9] = [
+    INKY_IMPRESSION_4,
+    INKY_IMPRESSION_5_7,
+    INKY_IMPRESSION_7_3,
+    INKY_IMPRESSION_7_3_SPECTRA_6,
+    WAVESHARE_7_3_ACEP,
+    WAVESHARE_7_3_SPECTRA_6,
+    TRI_COLOUR_RED,
+    TRI_COLOUR_YELLOW,
+    GREYSCALE_4,
+];
+
+impl Device {
+    /// Returns a copy of the profile for a panel mounted with the given orientation.
+    #[must_use]
+    pub const fn with_orientation(self, orientation: Orientation) -> Self {
+        Self {
+            orientation,
+            ..self
+        }
+    }
+
+    /// Returns the size images should be before packing, in their logical orientation.
+    #[must_use]
+    pub fn logical_size(&self) -> (u32, u32) {
+        self.orientation.input_size(self.width, self.height)
+    }
+
+    /// Returns the nominal palette.
+    #[must_use]
+    pub fn palette(&self) -> Palette {
+        self.palette.into()
+    }
+
+    /// Returns the colours the panel actually displays.
+    #[must_use]
+    pub fn measured_palette(&self) -> Palette {
+        self.measured_palette.into()
+    }
+
+    /// Blends the nominal and measured palettes, see [`Palette::from_blend`]. A saturation of 0
+    /// gives the measured palette and 1 the nominal one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if saturation is not 0 <= s <= 1.
+    pub fn blended_palette(&self, saturation: f32) -> Result<Palette> {
+        Palette::from_blend(self.measured_palette, self.palette, saturation)
+    }
+
+    /// Creates a paired palette that matches against a blend of the nominal and measured
+    /// palettes but outputs the nominal colours, see [`Palette::paired`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if saturation is not 0 <= s <= 1.
+    pub fn paired_palette(&self, saturation: f32) -> Result<Palette> {
+        let perceived: Vec<[u8; 3]> = self
+            .blended_palette(saturation)?
+            .get_colours()
+            .iter()
+            .map(|c| c.0)
+            .collect();
+        Palette::paired(&perceived, self.palette)
+    }
+
+    /// Packs palette indices, in row-major order for an image of the device's logical size (see
+    /// [`Device::logical_size`]), into the bytes the controller expects. Each row is padded to a
+    /// whole byte.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there isn't exactly one index per pixel.
+    pub fn pack(&self, indices: &[u8]) -> Result<Vec<u8>> {
+        fn code(codes: &[u8], idx: u8) -> u8 {
+            codes.get(usize::from(idx)).copied().unwrap_or(0)
+        }
+
+        let (width, height) = self.logical_size();
+        if indices.len() != width as usize * height as usize {
+            return Err(anyhow!(
+                "Expected {} indices for {}x{}, got: {}",
+                width as usize * height as usize,
+                width,
+                height,
+                indices.len()
+            ));
+        }
+        let (indices, width, _) = self.orientation.apply(indices, width, height);
+        if width == 0 {
+            return Ok(Vec::new());
+        }
+        let rows = indices.chunks(width as usize);
+
+        Ok(match self.packing {
+            Packing::Nibbles(codes) => rows
+                .flat_map(|row| row.chunks(2))
+                .map(|c| code(codes, c[0]) << 4 | c.get(1).map_or(0, |&i| code(codes, i)))
+                .collect(),
+            Packing::TwoBit(codes) => rows
+                .flat_map(|row| row.chunks(4))
+                .map(|c| {
+                    c.iter()
+                        .enumerate()
+                        .fold(0, |acc, (i, &idx)| acc | code(codes, idx) << (6 - 2 * i))
+                })
+                .collect(),
+            Packing::Planes(layout) => indices_to_planes(&indices, width, &layout).concat(),
+        })
+    }
+
+    /// Takes an arbitrary image to a buffer ready to send to the device.
+    ///
+    /// The image is scaled and cropped to fill the logical size, then quantised and
+    /// dithered against a blend of the nominal and measured palettes (see
+    /// [`Device::paired_palette`]) and packed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if saturation is not 0 <= s <= 1.
+    #[cfg(feature = "std")]
+    pub fn render(
+        &self,
+        img: &DynamicImage,
+        space: ColourSpace,
+        saturation: f32,
+    ) -> Result<Vec<u8>> {
+        let palette = self.paired_palette(saturation)?;
+        let (width, height) = self.logical_size();
+        let mut buf = img
+            .resize_to_fill(width, height, FilterType::Lanczos3)
+            .to_rgb8();
+        quantise_and_dither_image(&mut buf, &palette, space);
+        self.pack(&image_to_indices(&buf, &palette))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orientation::Rotation;
+    use alloc::vec;
+
+    #[test]
+    fn saturation_blends_towards_nominal() {
+        let device = INKY_IMPRESSION_7_3_SPECTRA_6;
+        assert_eq!(
+            device.blended_palette(0.0).unwrap().get_colours(),
+            device.measured_palette().get_colours()
+        );
+        assert_eq!(
+            device.blended_palette(1.0).unwrap().get_colours(),
+            device.palette().get_colours()
+        );
+        let paired = device.paired_palette(0.0).unwrap();
+        assert_eq!(
+            paired.get_colours(),
+            device.measured_palette().get_colours()
+        );
+        assert_eq!(paired.get_output_colours(), device.palette().get_colours());
+    }
+
+    #[test]
+    fn spectra_6_skips_code_4() {
+        let device = Device {
+            width: 6,
+            height: 1,
+            ..WAVESHARE_7_3_SPECTRA_6
+        };
+        assert_eq!(
+            device.pack(&[0, 1, 2, 3, 4, 5]).unwrap(),
+            vec![0x01, 0x23, 0x56]
+        );
+    }
+
+    #[test]
+    fn packs_in_native_orientation() {
+        // a 2x4 portrait image on a 4x2 landscape controller
+        let device = Device {
+            width: 4,
+            height: 2,
+            ..WAVESHARE_7_3_ACEP
+        }
+        .with_orientation(Orientation::rotated(Rotation::Clockwise270));
+        assert_eq!(device.logical_size(), (2, 4));
+        assert_eq!(
+            device.pack(&[0, 1, 2, 3, 4, 5, 6, 0]).unwrap(),
+            vec![0x13, 0x50, 0x02, 0x46]
+        );
+    }
+
+    #[test]
+    fn tri_colour_planes() {
+        let device = Device {
+            width: 3,
+            height: 2,
+            ..TRI_COLOUR_RED
+        };
+        assert_eq!(
+            device.pack(&[0, 1, 2, 2, 1, 0]).unwrap(),
+            vec![0b0110_0000, 0b1100_0000, 0b0010_0000, 0b1000_0000]
+        );
+    }
+
+    #[test]
+    fn pads_each_row() {
+        // 3 pixels a row, so each row ends in half a byte
+        let device = Device {
+            width: 3,
+            height: 2,
+            ..WAVESHARE_7_3_ACEP
+        };
+        assert_eq!(
+            device.pack(&[1, 2, 3, 4, 5, 6]).unwrap(),
+            vec![0x12, 0x30, 0x45, 0x60]
+        );
+        assert!(INKY_IMPRESSION_4.pack(&[0, 1, 2]).is_err());
+    }
+}
@@ -0,0 +1,985 @@
+//! Error diffusion dithering.
+
+#[cfg(feature = "std")]
+use crate::DitherPixel;
+use crate::float::sqrt;
+#[cfg(feature = "std")]
+use crate::parallel::parallel_map;
+#[cfg(feature = "std")]
+use crate::pixel::{row_major, write_indices};
+use crate::rng::SplitMix64;
+use crate::space::{
+    cielab_to_srgb, linear_to_srgb, luma, oklab_to_srgb, srgb_to_cielab, srgb_to_linear,
+    srgb_to_oklab,
+};
+use crate::{ColourSpace, Palette, Rgb, f32_to_u8};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use image::GenericImage;
+
+/// An error diffusion kernel.
+#[derive(Copy, Clone, Debug)]
+pub struct Kernel {
+    /// `(dx, dy, weight)` of each neighbour the error is pushed to, relative to the current
+    /// pixel. Neighbours must come after the current pixel in raster order, i.e. `dy > 0`, or
+    /// `dy == 0` and `dx > 0`.
+    pub weights: &'static [(i32, i32, f32)],
+}
+
+/// <https://en.wikipedia.org/wiki/Floyd%E2%80%93Steinberg_dithering>
+pub const FLOYD_STEINBERG: Kernel = Kernel {
+    weights: &[
+        (1, 0, 7.0 / 16.0),
+        (-1, 1, 3.0 / 16.0),
+        (0, 1, 5.0 / 16.0),
+        (1, 1, 1.0 / 16.0),
+    ],
+};
+
+/// Error diffusion whose coefficients change with the intensity of each source pixel, which
+/// breaks up the regular patterns fixed kernels leave in mid-tones.
+///
+/// Intensity is the luma of the source pixel. Rows are scanned in alternating directions, and
+/// [`DiffusionOptions::kernel`] is ignored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Variable {
+    /// <https://perso.liris.cnrs.fr/victor.ostromoukhov/publications/pdf/SIGGRAPH01_varcoeffED.pdf>
+    Ostromoukhov,
+    /// Ostromoukhov's coefficients with the quantisation threshold modulated by seeded noise,
+    /// strongest in the mid-tones, after Zhou and Fang's "Improving mid-tone quality of
+    /// variable-coefficient error diffusion using threshold modulation".
+    ZhouFang { seed: u64 },
+}
+
+/// Ostromoukhov's coefficients for intensities 0 to 127 as `[right, down left, down, sum]`,
+/// mirrored for 128 to 255.
+#[rustfmt::skip]
+const OSTROMOUKHOV: [[u16; 4]; 128] = [
+    [13, 0, 5, 18], [13, 0, 5, 18], [21, 0, 10, 31], [7, 0, 4, 11], [8, 0, 5, 13], [47, 3, 28, 78],
+    [23, 3, 13, 39], [15, 3, 8, 26], [22, 6, 11, 39], [43, 15, 20, 78], [7, 3, 3, 13],
+    [501, 224, 211, 936], [249, 116, 103, 468], [165, 80, 67, 312], [123, 62, 49, 234],
+    [489, 256, 191, 936], [81, 44, 31, 156], [483, 272, 181, 936], [60, 35, 22, 117],
+    [53, 32, 19, 104], [237, 148, 83, 468], [471, 304, 161, 936], [3, 2, 1, 6],
+    [481, 314, 185, 980], [354, 226, 155, 735], [1389, 866, 685, 2940], [227, 138, 125, 490],
+    [267, 158, 163, 588], [327, 188, 220, 735], [61, 34, 45, 140], [627, 338, 505, 1470],
+    [1227, 638, 1075, 2940], [20, 10, 19, 49], [1937, 1000, 1767, 4704], [977, 520, 855, 2352],
+    [657, 360, 551, 1568], [71, 40, 57, 168], [2005, 1160, 1539, 4704], [337, 200, 247, 784],
+    [2039, 1240, 1425, 4704], [257, 160, 171, 588], [691, 440, 437, 1568], [1045, 680, 627, 2352],
+    [301, 200, 171, 672], [177, 120, 95, 392], [2141, 1480, 1083, 4704], [1079, 760, 513, 2352],
+    [725, 520, 323, 1568], [137, 100, 57, 294], [2209, 1640, 855, 4704], [53, 40, 19, 112],
+    [2243, 1720, 741, 4704], [565, 440, 171, 1176], [759, 600, 209, 1568], [1147, 920, 285, 2352],
+    [2311, 1880, 513, 4704], [97, 80, 19, 196], [335, 280, 57, 672], [1181, 1000, 171, 2352],
+    [793, 680, 95, 1568], [599, 520, 57, 1176], [2413, 2120, 171, 4704], [405, 360, 19, 784],
+    [2447, 2200, 57, 4704], [11, 10, 0, 21], [158, 151, 3, 312], [178, 179, 7, 364],
+    [1030, 1091, 63, 2184], [248, 277, 21, 546], [318, 375, 35, 728], [458, 571, 63, 1092],
+    [878, 1159, 147, 2184], [5, 7, 1, 13], [172, 181, 37, 390], [97, 76, 22, 195],
+    [72, 41, 17, 130], [119, 47, 29, 195], [4, 1, 1, 6], [4, 1, 1, 6], [4, 1, 1, 6], [4, 1, 1, 6],
+    [4, 1, 1, 6], [4, 1, 1, 6], [4, 1, 1, 6], [4, 1, 1, 6], [4, 1, 1, 6], [65, 18, 17, 100],
+    [95, 29, 26, 150], [185, 62, 53, 300], [30, 11, 9, 50], [35, 14, 11, 60], [85, 37, 28, 150],
+    [55, 26, 19, 100], [80, 41, 29, 150], [155, 86, 59, 300], [5, 3, 2, 10], [5, 3, 2, 10],
+    [5, 3, 2, 10], [5, 3, 2, 10], [5, 3, 2, 10], [5, 3, 2, 10], [5, 3, 2, 10], [5, 3, 2, 10],
+    [5, 3, 2, 10], [5, 3, 2, 10], [5, 3, 2, 10], [5, 3, 2, 10], [5, 3, 2, 10], [305, 176, 119, 600],
+    [155, 86, 59, 300], [105, 56, 39, 200], [80, 41, 29, 150], [65, 32, 23, 120], [55, 26, 19, 100],
+    [335, 152, 113, 600], [85, 37, 28, 150], [115, 48, 37, 200], [35, 14, 11, 60],
+    [355, 136, 109, 600], [30, 11, 9, 50], [365, 128, 107, 600], [185, 62, 53, 300], [25, 8, 7, 40],
+    [95, 29, 26, 150], [385, 112, 103, 600], [65, 18, 17, 100], [395, 104, 101, 600], [4, 1, 1, 6],
+];
+
+/// Intensities and threshold modulation strengths between which Zhou–Fang modulation is
+/// interpolated, mirrored above 127.
+const MODULATION: [(u8, f32); 9] = [
+    (0, 0.0),
+    (44, 0.34),
+    (64, 0.5),
+    (85, 1.0),
+    (95, 0.17),
+    (102, 0.5),
+    (107, 0.7),
+    (112, 0.79),
+    (127, 1.0),
+];
+
+fn mirror_level(level: u8) -> u8 {
+    if level > 127 { 255 - level } else { level }
+}
+
+/// Weights of the variable-coefficient kernel for a source intensity.
+fn variable_weights(level: u8) -> [(i32, i32, f32); 3] {
+    let [right, down_left, down, sum] =
+        OSTROMOUKHOV[usize::from(mirror_level(level))].map(f32::from);
+    [
+        (1, 0, right / sum),
+        (-1, 1, down_left / sum),
+        (0, 1, down / sum),
+    ]
+}
+
+/// Threshold modulation strength for a source intensity, in [0, 1].
+fn modulation(level: u8) -> f32 {
+    let level = mirror_level(level);
+    MODULATION
+        .windows(2)
+        .find(|pair| level <= pair[1].0)
+        .map_or(1.0, |pair| {
+            let ((l0, m0), (l1, m1)) = (pair[0], pair[1]);
+            let t = f32::from(level - l0) / f32::from(l1 - l0);
+            m0 + (m1 - m0) * t
+        })
+}
+
+/// What happens to error pushed past the edges of the image.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Boundary {
+    /// Error pushed outside the image is lost.
+    Drop,
+    /// The weights of neighbours inside the image are scaled up so that all the error is kept.
+    #[default]
+    Renormalise,
+    /// Neighbours wrap around to the opposite edge, so the result tiles seamlessly. Error pushed
+    /// past the bottom edge is carried into the top rows by dithering the image twice. Error
+    /// pushed along the row past its end, onto pixels already quantised, continues onto the next
+    /// row as a raster scan does.
+    Wrap,
+}
+
+impl Boundary {
+    /// Returns how much the weights of a pixel's neighbours are scaled by, given which are
+    /// inside the image.
+    fn scale<F: Fn(i32, i32) -> bool>(self, weights: &[(i32, i32, f32)], in_bounds: F) -> f32 {
+        match self {
+            Self::Renormalise => {
+                let total: f32 = weights.iter().map(|&(_, _, weight)| weight).sum();
+                let inside: f32 = weights
+                    .iter()
+                    .filter(|&&(dx, dy, _)| in_bounds(dx, dy))
+                    .map(|&(_, _, weight)| weight)
+                    .sum();
+                if inside > 0.0 { total / inside } else { 0.0 }
+            }
+            Self::Drop | Self::Wrap => 1.0,
+        }
+    }
+}
+
+/// Colour space error is measured and diffused in.
+///
+/// Pixels are converted into it once on input, and palette colours once per call. Matching
+/// against the palette still uses whichever [`ColourSpace`] is given.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ErrorSpace {
+    /// Gamma encoded sRGB. This biases mid-tones, so dithered output looks too dark.
+    #[default]
+    Srgb,
+    /// Linear light RGB, so the average light given off by an area matches the source.
+    LinearRgb,
+    /// <https://en.wikipedia.org/wiki/CIELAB_color_space>
+    CIELAB,
+    /// <https://bottosson.github.io/posts/oklab/>
+    OKLab,
+}
+
+impl ErrorSpace {
+    /// Converts sRGB with channels in [0, 255] into this space.
+    pub(crate) fn to_space(self, c: [f32; 3]) -> [f32; 3] {
+        match self {
+            Self::Srgb => c,
+            // kept in [0, 255] so it clamps like sRGB
+            Self::LinearRgb => c.map(|v| srgb_to_linear(v / 255.0) * 255.0),
+            Self::CIELAB => srgb_to_cielab(c),
+            Self::OKLab => srgb_to_oklab(c),
+        }
+    }
+
+    /// Converts a colour in this space back to sRGB with channels nominally in [0, 255].
+    pub(crate) fn to_srgb(self, c: [f32; 3]) -> [f32; 3] {
+        match self {
+            Self::Srgb => c,
+            Self::LinearRgb => c.map(|v| linear_to_srgb(v / 255.0) * 255.0),
+            Self::CIELAB => cielab_to_srgb(c),
+            Self::OKLab => oklab_to_srgb(c),
+        }
+    }
+
+    /// Returns the colours of a palette in this space, in palette order.
+    pub(crate) fn palette_coordinates(self, palette: &Palette) -> Vec<[f32; 3]> {
+        palette
+            .get_colours()
+            .iter()
+            .map(|c| self.to_space(c.0.map(f32::from)))
+            .collect()
+    }
+
+    /// Returns the luma of a colour in this space, which chooses [`Variable`] kernels.
+    pub(crate) fn level(self, c: [f32; 3]) -> u8 {
+        luma(Rgb(self.to_srgb(c).map(f32_to_u8)))
+    }
+
+    /// Clamps a colour in this space to the sRGB gamut.
+    pub(crate) fn clamp(self, c: [f32; 3]) -> [f32; 3] {
+        match self {
+            Self::Srgb | Self::LinearRgb => c.map(|v| v.clamp(0.0, 255.0)),
+            Self::CIELAB | Self::OKLab => {
+                self.to_space(self.to_srgb(c).map(|v| v.clamp(0.0, 255.0)))
+            }
+        }
+    }
+}
+
+/// Softens very large quantisation errors, which otherwise smear across flat areas when the
+/// palette is small or irregular.
+///
+/// The part of an error's magnitude above `threshold` is scaled by `factor`.
+#[derive(Copy, Clone, Debug)]
+pub struct ErrorDecay {
+    pub threshold: f32,
+    pub factor: f32,
+}
+
+impl ErrorDecay {
+    fn apply(self, err: [f32; 3]) -> [f32; 3] {
+        let magnitude = sqrt(err.iter().map(|e| e * e).sum::<f32>());
+        if magnitude <= self.threshold {
+            return err;
+        }
+        let decayed = self.threshold + (magnitude - self.threshold) * self.factor;
+        err.map(|e| e * decayed / magnitude)
+    }
+}
+
+/// Options for error diffusion.
+#[derive(Copy, Clone, Debug)]
+pub struct DiffusionOptions {
+    pub kernel: Kernel,
+    /// Replaces the kernel with one chosen per pixel.
+    pub variable: Option<Variable>,
+    pub boundary: Boundary,
+    pub error_space: ErrorSpace,
+    /// Lets pixels with accumulated error go outside [0, 255] before they're matched against the
+    /// palette. Otherwise they're clamped first, and the error is measured from the clamped
+    /// value.
+    pub allow_overshoot: bool,
+    /// Fraction of the error that is diffused, between 0 (plain quantisation) and 1.
+    pub strength: f32,
+    /// Largest error diffused on each channel, in the units of the error space. Limiting this
+    /// stops colour bleeding across hard edges.
+    pub error_clamp: Option<[f32; 3]>,
+    pub decay: Option<ErrorDecay>,
+}
+
+impl Default for DiffusionOptions {
+    fn default() -> Self {
+        Self {
+            kernel: FLOYD_STEINBERG,
+            variable: None,
+            boundary: Boundary::default(),
+            error_space: ErrorSpace::default(),
+            allow_overshoot: false,
+            strength: 1.0,
+            error_clamp: None,
+            decay: None,
+        }
+    }
+}
+
+impl DiffusionOptions {
+    /// Returns how many rows below the current one error can be diffused into.
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn rows_below(&self) -> usize {
+        if self.variable.is_some() {
+            return 1;
+        }
+        let rows = self
+            .kernel
+            .weights
+            .iter()
+            .map(|&(_, dy, _)| dy.max(0) as usize)
+            .max()
+            .unwrap_or(0);
+        // wrapped error from the end of a row goes to the next one
+        if self.boundary == Boundary::Wrap {
+            rows.max(1)
+        } else {
+            rows
+        }
+    }
+
+    /// Returns the error diffused after quantising `old` to `new`, with decay, clamping and
+    /// strength applied.
+    fn shape_error(&self, old: [f32; 3], new: [f32; 3]) -> [f32; 3] {
+        let err: [f32; 3] = core::array::from_fn(|c| old[c] - new[c]);
+        let err = self.decay.map_or(err, |decay| decay.apply(err));
+        let err = self.error_clamp.map_or(err, |limit| {
+            core::array::from_fn(|c| err[c].clamp(-limit[c].abs(), limit[c].abs()))
+        });
+        let strength = self.strength.clamp(0.0, 1.0);
+        err.map(|e| e * strength)
+    }
+}
+
+/// Quantises an image using the given palette and colour space, diffusing the quantisation error
+/// to neighbouring pixels as described by the options.
+///
+/// Pixels are read at their full precision, see [`DitherPixel`].
+#[cfg(feature = "std")]
+pub fn diffuse<I>(buf: &mut I, palette: &Palette, space: ColourSpace, options: &DiffusionOptions)
+where
+    I: GenericImage,
+    I::Pixel: DitherPixel,
+{
+    let (width, height) = buf.dimensions();
+    let source = row_major(buf).map(|p| p.to_srgb()).collect();
+    diffuse_srgb(
+        source,
+        width,
+        height,
+        palette,
+        space,
+        options,
+        |x, y, colour| {
+            buf.put_pixel(x, y, I::Pixel::from_palette(colour));
+        },
+    );
+}
+
+/// Like [`diffuse`], for a `width` by `height` image stored as row-major pixels.
+pub fn diffuse_pixels(
+    pixels: &mut [[u8; 3]],
+    width: u32,
+    height: u32,
+    palette: &Palette,
+    space: ColourSpace,
+    options: &DiffusionOptions,
+) {
+    let source = pixels.iter().map(|p| p.map(f32::from)).collect();
+    diffuse_srgb(
+        source,
+        width,
+        height,
+        palette,
+        space,
+        options,
+        |x, y, colour| {
+            pixels[y as usize * width as usize + x as usize] = colour.0;
+        },
+    );
+}
+
+/// Diffuses row-major sRGB pixels, passing the output colour of each to `put`.
+fn diffuse_srgb(
+    mut work: Vec<[f32; 3]>,
+    width: u32,
+    height: u32,
+    palette: &Palette,
+    space: ColourSpace,
+    options: &DiffusionOptions,
+    mut put: impl FnMut(u32, u32, Rgb<u8>),
+) {
+    let error_space = options.error_space;
+    let colours = error_space.palette_coordinates(palette);
+    for pixel in &mut work {
+        *pixel = error_space.to_space(*pixel);
+    }
+    diffuse_with(&mut work, width, height, options, |x, y, pixel| {
+        let idx = palette.closest_idx_f32(space, error_space.to_srgb(pixel));
+        put(x, y, palette.get_output_colours()[idx]);
+        colours[idx]
+    });
+}
+
+/// Like [`diffuse`], but splits the image into tiles of at least `tile` pixels square that are
+/// dithered in a wavefront, so that tiles which don't depend on each other run on separate
+/// threads.
+///
+/// A tile starts once the tiles to its left, above and above right are done. Error that would
+/// flow back into the finished tile to the left is handled like error at the edge of the image,
+/// so the result differs slightly from [`diffuse`] along tile edges. Variable kernels and
+/// [`Boundary::Wrap`] need the whole image in order, so they fall back to [`diffuse`].
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[cfg(feature = "std")]
+pub fn diffuse_wavefront<I>(
+    buf: &mut I,
+    palette: &Palette,
+    space: ColourSpace,
+    options: &DiffusionOptions,
+    tile: u32,
+) where
+    I: GenericImage,
+    I::Pixel: DitherPixel,
+{
+    if options.variable.is_some() || options.boundary == Boundary::Wrap {
+        diffuse(buf, palette, space, options);
+        return;
+    }
+
+    let (width, height) = buf.dimensions();
+    let weights = options.kernel.weights;
+    // tiles must be larger than the kernel, so error only reaches neighbouring tiles
+    let reach_x = weights
+        .iter()
+        .map(|&(dx, _, _)| dx.unsigned_abs())
+        .max()
+        .unwrap_or(0);
+    let reach_y = weights
+        .iter()
+        .map(|&(_, dy, _)| dy.unsigned_abs())
+        .max()
+        .unwrap_or(0);
+    let (tile_w, tile_h) = (tile.max(reach_x + 1), tile.max(reach_y + 1));
+    let (cols, rows) = (width.div_ceil(tile_w), height.div_ceil(tile_h));
+    if cols == 0 || rows == 0 {
+        return;
+    }
+
+    let error_space = options.error_space;
+    let colours = error_space.palette_coordinates(palette);
+    let mut work: Vec<[f32; 3]> = row_major(buf)
+        .map(|p| error_space.to_space(p.to_srgb()))
+        .collect();
+    let mut indices = vec![0; work.len()];
+
+    // tile (tx, ty) runs at step tx + 2 * ty, after all the tiles it takes error from
+    for step in 0..cols + 2 * (rows - 1) {
+        let tiles: Vec<(u32, u32)> = (0..rows)
+            .filter_map(|ty| {
+                let tx = step.checked_sub(2 * ty)?;
+                (tx < cols).then_some((tx * tile_w, ty * tile_h))
+            })
+            .collect();
+        let results = parallel_map(tiles.len(), |t| {
+            let (x0, y0) = tiles[t];
+            let region = (x0, y0, (x0 + tile_w).min(width), (y0 + tile_h).min(height));
+            dither_tile(
+                &work,
+                (width, height),
+                region,
+                (reach_x, reach_y),
+                options,
+                |c| {
+                    let idx = palette.closest_idx_f32(space, error_space.to_srgb(c));
+                    (idx, colours[idx])
+                },
+            )
+        });
+        for tile in results {
+            for (i, idx) in tile.quantised {
+                indices[i] = idx;
+            }
+            for (i, err) in tile.spilled {
+                for (c, e) in work[i].iter_mut().zip(err) {
+                    *c += e;
+                }
+            }
+        }
+    }
+
+    write_indices(buf, indices, palette);
+}
+
+/// A dithered tile.
+#[cfg(feature = "std")]
+struct Tile {
+    /// Image index and palette index of each pixel.
+    quantised: Vec<(usize, usize)>,
+    /// Error that spilled into the tiles right of and below it.
+    spilled: Vec<(usize, [f32; 3])>,
+}
+
+/// Dithers the pixels in `(x0, y0, x1, y1)`.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[cfg(feature = "std")]
+fn dither_tile<Q>(
+    work: &[[f32; 3]],
+    (width, height): (u32, u32),
+    (x0, y0, x1, y1): (u32, u32, u32, u32),
+    (reach_x, reach_y): (u32, u32),
+    options: &DiffusionOptions,
+    quantise: Q,
+) -> Tile
+where
+    Q: Fn([f32; 3]) -> (usize, [f32; 3]),
+{
+    let (w, h) = (i64::from(width), i64::from(height));
+    // error added to the tile and the margin it can spill into
+    let (ex0, ex1, ey1) = (
+        i64::from(x0.saturating_sub(reach_x)),
+        i64::from((x1 + reach_x).min(width)),
+        i64::from((y1 + reach_y).min(height)),
+    );
+    let ey0 = i64::from(y0);
+    let local = |x: i64, y: i64| ((y - ey0) * (ex1 - ex0) + x - ex0) as usize;
+    let mut added = vec![[0.0f32; 3]; local(ex0, ey1)];
+    let mut quantised = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+    let weights = options.kernel.weights;
+
+    for y in i64::from(y0)..i64::from(y1) {
+        for x in i64::from(x0)..i64::from(x1) {
+            let i = (y * w + x) as usize;
+            let value: [f32; 3] = core::array::from_fn(|c| work[i][c] + added[local(x, y)][c]);
+            let old = if options.allow_overshoot {
+                value
+            } else {
+                options.error_space.clamp(value)
+            };
+            let (idx, new) = quantise(old);
+            quantised.push((i, idx));
+
+            let err = options.shape_error(old, new);
+            if err == [0.0; 3] {
+                continue;
+            }
+            let in_bounds = |dx: i32, dy: i32| {
+                let (nx, ny) = (x + i64::from(dx), y + i64::from(dy));
+                let finished = nx < i64::from(x0) && ny < i64::from(y1);
+                (0..w).contains(&nx) && (0..h).contains(&ny) && !finished
+            };
+            let scale = options.boundary.scale(weights, in_bounds);
+            for &(dx, dy, weight) in weights {
+                if !in_bounds(dx, dy) {
+                    continue;
+                }
+                let (nx, ny) = (x + i64::from(dx), y + i64::from(dy));
+                for (c, e) in added[local(nx, ny)].iter_mut().zip(err) {
+                    *c += e * weight * scale;
+                }
+            }
+        }
+    }
+
+    let inside = |x: i64, y: i64| {
+        (i64::from(x0)..i64::from(x1)).contains(&x) && (i64::from(y0)..i64::from(y1)).contains(&y)
+    };
+    let spilled = (ey0..ey1)
+        .flat_map(|y| (ex0..ex1).map(move |x| (x, y)))
+        .filter(|&(x, y)| !inside(x, y))
+        .map(|(x, y)| ((y * w + x) as usize, added[local(x, y)]))
+        .filter(|&(_, err)| err != [0.0; 3])
+        .collect();
+    Tile { quantised, spilled }
+}
+
+/// Diffuses error over a buffer of colours in the options' error space, in raster order, or in
+/// alternating directions for [`Variable`] kernels.
+///
+/// `quantise` is given each pixel's position and its value with accumulated error, and returns
+/// the value it was quantised to. Error is only ever accumulated in `work`, so it's never rounded
+/// or clipped between pixels.
+pub(crate) fn diffuse_with<Q>(
+    work: &mut [[f32; 3]],
+    width: u32,
+    height: u32,
+    options: &DiffusionOptions,
+    quantise: Q,
+) where
+    Q: FnMut(u32, u32, [f32; 3]) -> [f32; 3],
+{
+    diffuse_masked(work, &[], width, height, options, quantise);
+}
+
+/// Like [`diffuse_with`], but only pixels where `opaque` is set are quantised, and they're
+/// treated as the whole image, so error never flows into or out of the others. An empty mask
+/// leaves every pixel opaque.
+pub(crate) fn diffuse_masked<Q>(
+    work: &mut [[f32; 3]],
+    opaque: &[bool],
+    width: u32,
+    height: u32,
+    options: &DiffusionOptions,
+    mut quantise: Q,
+) where
+    Q: FnMut(u32, u32, [f32; 3]) -> [f32; 3],
+{
+    // variable kernels are chosen by the source intensity, before any error is added
+    let levels: Vec<u8> = if options.variable.is_some() {
+        work.iter().map(|&c| options.error_space.level(c)).collect()
+    } else {
+        Vec::new()
+    };
+
+    if options.boundary == Boundary::Wrap {
+        // dither a copy just to find the error that falls off the bottom, and add it to the top
+        // rows before dithering for real
+        let mut primer = work.to_vec();
+        let carried = diffuse_pass(
+            &mut primer,
+            opaque,
+            width,
+            height,
+            options,
+            &levels,
+            &mut quantise,
+        );
+        for (i, err) in carried {
+            if opaque.get(i) == Some(&false) {
+                continue;
+            }
+            for (c, e) in work[i].iter_mut().zip(err) {
+                *c += e;
+            }
+        }
+    }
+    diffuse_pass(work, opaque, width, height, options, &levels, &mut quantise);
+}
+
+/// Runs a single pass, returning the error that wrapped past the bottom edge when the
+/// boundary is [`Boundary::Wrap`].
+fn diffuse_pass<Q>(
+    work: &mut [[f32; 3]],
+    opaque: &[bool],
+    width: u32,
+    height: u32,
+    options: &DiffusionOptions,
+    levels: &[u8],
+    quantise: &mut Q,
+) -> Vec<(usize, [f32; 3])>
+where
+    Q: FnMut(u32, u32, [f32; 3]) -> [f32; 3],
+{
+    let w = width as usize;
+    let mut pass = Pass::new(*options, width, height);
+    if w == 0 {
+        return pass.carried;
+    }
+    for (y, start) in (0..height).zip((0..work.len()).step_by(w)) {
+        let mut window: Vec<&mut [[f32; 3]]> = work[start..]
+            .chunks_mut(w)
+            .take(options.rows_below() + 1)
+            .collect();
+        let masks: Vec<&[bool]> = opaque
+            .get(start..)
+            .unwrap_or_default()
+            .chunks(w)
+            .take(window.len())
+            .collect();
+        let levels = levels.get(start..start + w).unwrap_or_default();
+        pass.row(y, &mut window, levels, &masks, quantise);
+    }
+    pass.carried
+}
+
+/// A pass of error diffusion over an image, advanced one row at a time.
+pub(crate) struct Pass {
+    options: DiffusionOptions,
+    width: u32,
+    height: u32,
+    rng: Option<SplitMix64>,
+    /// Error that wrapped past the bottom edge, for [`Boundary::Wrap`].
+    carried: Vec<(usize, [f32; 3])>,
+}
+
+impl Pass {
+    pub(crate) fn new(options: DiffusionOptions, width: u32, height: u32) -> Self {
+        let rng = match options.variable {
+            Some(Variable::ZhouFang { seed }) => Some(SplitMix64::new(seed)),
+            _ => None,
+        };
+        Self {
+            options,
+            width,
+            height,
+            rng,
+            carried: Vec::new(),
+        }
+    }
+
+    /// Quantises row `y`, `window[0]`, diffusing its error into the rows below it in the rest of
+    /// the window, which must hold [`DiffusionOptions::rows_below`] rows or reach the bottom of
+    /// the image.
+    ///
+    /// `levels` is the source intensity of each pixel in the row, needed by [`Variable`] kernels.
+    /// `opaque` masks the rows in the window, see [`diffuse_masked`], or is empty.
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn row<Q>(
+        &mut self,
+        y: u32,
+        window: &mut [&mut [[f32; 3]]],
+        levels: &[u8],
+        opaque: &[&[bool]],
+        quantise: &mut Q,
+    ) where
+        Q: FnMut(u32, u32, [f32; 3]) -> [f32; 3],
+    {
+        let options = &self.options;
+        let (w, h) = (i64::from(self.width), i64::from(self.height));
+        let y = i64::from(y);
+
+        // variable kernels scan odd rows right to left, mirroring the kernel
+        let reversed = options.variable.is_some() && y % 2 == 1;
+        let mirror = if reversed { -1 } else { 1 };
+        // only called for pixels inside the image, which are all in the window
+        let is_opaque =
+            |nx: i64, ny: i64| opaque.is_empty() || opaque[(ny - y) as usize][nx as usize];
+        for step in 0..w {
+            let x = if reversed { w - 1 - step } else { step };
+            let i = x as usize;
+            if !is_opaque(x, y) {
+                continue;
+            }
+            let variable;
+            let weights = if options.variable.is_some() {
+                variable = variable_weights(levels[i]);
+                &variable[..]
+            } else {
+                options.kernel.weights
+            };
+
+            let old = if options.allow_overshoot {
+                window[0][i]
+            } else {
+                options.error_space.clamp(window[0][i])
+            };
+            let new = match &mut self.rng {
+                Some(rng) => {
+                    // moving the threshold is the same as moving the pixel the other way
+                    let shift = (rng.next_f32() - 0.5) * 128.0 * modulation(levels[i]);
+                    let space = options.error_space;
+                    let probe = space.to_space(space.to_srgb(old).map(|v| v + shift));
+                    quantise(x as u32, y as u32, probe)
+                }
+                None => quantise(x as u32, y as u32, old),
+            };
+
+            let err = options.shape_error(old, new);
+            if err == [0.0; 3] {
+                continue;
+            }
+
+            let in_bounds = |dx: i32, dy: i32| {
+                let (nx, ny) = (x + i64::from(dx * mirror), y + i64::from(dy));
+                (0..w).contains(&nx) && (0..h).contains(&ny) && is_opaque(nx, ny)
+            };
+            let scale = options.boundary.scale(weights, in_bounds);
+
+            for &(dx, dy, weight) in weights {
+                let (nx, ny) = (x + i64::from(dx * mirror), y + i64::from(dy));
+                let (nx, ny) = match options.boundary {
+                    // the rest of this row is already quantised
+                    Boundary::Wrap if dy == 0 && !(0..w).contains(&nx) => (nx.rem_euclid(w), y + 1),
+                    Boundary::Wrap => (nx.rem_euclid(w), ny),
+                    Boundary::Drop | Boundary::Renormalise => (nx, ny),
+                };
+                if !(0..w).contains(&nx) || ny < y {
+                    continue;
+                }
+                let weight = weight * scale;
+                if ny >= h {
+                    if options.boundary == Boundary::Wrap {
+                        let j = ((ny % h) * w + nx) as usize;
+                        self.carried.push((j, err.map(|e| e * weight)));
+                    }
+                    continue;
+                }
+                if !is_opaque(nx, ny) {
+                    continue;
+                }
+                for (c, e) in window[(ny - y) as usize][nx as usize].iter_mut().zip(err) {
+                    *c += e * weight;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::fixtures::{four_colours, gradient};
+    use image::{Rgb, RgbImage};
+
+    fn white_count(buf: &RgbImage) -> usize {
+        buf.pixels().filter(|p| p.0[0] == 255).count()
+    }
+
+    fn dither(buf: &mut RgbImage, boundary: Boundary) {
+        let options = DiffusionOptions {
+            boundary,
+            ..DiffusionOptions::default()
+        };
+        diffuse(buf, &Palette::monochrome(), ColourSpace::RGB, &options);
+    }
+
+    #[test]
+    fn diffuses_error() {
+        // without diffusion every pixel would quantise to black
+        let mut buf = RgbImage::from_pixel(32, 32, Rgb([64, 64, 64]));
+        dither(&mut buf, Boundary::Drop);
+        let white = white_count(&buf);
+        assert!((230..=280).contains(&white), "{white}");
+    }
+
+    #[test]
+    fn renormalises_at_edges() {
+        // a single column only has the neighbour below, so Floyd–Steinberg drops most error
+        let mut dropped = RgbImage::from_pixel(1, 64, Rgb([64, 64, 64]));
+        let mut renormalised = dropped.clone();
+        dither(&mut dropped, Boundary::Drop);
+        let options = DiffusionOptions {
+            allow_overshoot: true,
+            ..DiffusionOptions::default()
+        };
+        diffuse(
+            &mut renormalised,
+            &Palette::monochrome(),
+            ColourSpace::RGB,
+            &options,
+        );
+        assert_eq!(white_count(&dropped), 0);
+        assert_eq!(white_count(&renormalised), 16);
+    }
+
+    #[test]
+    fn linear_light_matches_brightness() {
+        // sRGB 128 is about 22% of the light of white
+        let mut buf = RgbImage::from_pixel(32, 32, Rgb([128, 128, 128]));
+        let options = DiffusionOptions {
+            error_space: ErrorSpace::LinearRgb,
+            allow_overshoot: true,
+            ..DiffusionOptions::default()
+        };
+        diffuse(&mut buf, &Palette::monochrome(), ColourSpace::RGB, &options);
+        let white = white_count(&buf);
+        assert!((200..=245).contains(&white), "{white}");
+
+        for error_space in [ErrorSpace::CIELAB, ErrorSpace::OKLab] {
+            let mut buf = RgbImage::from_pixel(32, 32, Rgb([128, 128, 128]));
+            let options = DiffusionOptions {
+                error_space,
+                ..DiffusionOptions::default()
+            };
+            diffuse(&mut buf, &Palette::monochrome(), ColourSpace::RGB, &options);
+            assert!(white_count(&buf) > 0, "{error_space:?}");
+        }
+    }
+
+    #[test]
+    fn strength_and_clamping() {
+        let palette = Palette::monochrome();
+        let source = RgbImage::from_fn(16, 16, |x, y| {
+            let v = u8::try_from((x + y) * 8).unwrap();
+            Rgb([v, v, v])
+        });
+        let mut quantised = source.clone();
+        crate::quantise_image(&mut quantised, &palette, ColourSpace::RGB);
+
+        let none = [
+            DiffusionOptions {
+                strength: 0.0,
+                ..DiffusionOptions::default()
+            },
+            DiffusionOptions {
+                error_clamp: Some([0.0; 3]),
+                ..DiffusionOptions::default()
+            },
+            DiffusionOptions {
+                decay: Some(ErrorDecay {
+                    threshold: 0.0,
+                    factor: 0.0,
+                }),
+                ..DiffusionOptions::default()
+            },
+        ];
+        for options in none {
+            let mut buf = source.clone();
+            diffuse(&mut buf, &palette, ColourSpace::RGB, &options);
+            assert_eq!(buf, quantised, "{options:?}");
+        }
+    }
+
+    #[test]
+    fn overshoot() {
+        // a dark pixel next to a bright one pushes the bright one past 255
+        let mut buf =
+            RgbImage::from_raw(3, 1, vec![100, 100, 100, 250, 250, 250, 40, 40, 40]).unwrap();
+        let mut clamped = buf.clone();
+        let options = DiffusionOptions {
+            allow_overshoot: true,
+            ..DiffusionOptions::default()
+        };
+        diffuse(&mut buf, &Palette::monochrome(), ColourSpace::RGB, &options);
+        dither(&mut clamped, Boundary::Renormalise);
+        // 100 -> black, 250 + 100 = 350 -> white, 40 + 95 -> white
+        // with clamping, 250 + 100 -> 255 -> white, 40 + 0 -> black
+        assert_eq!(white_count(&buf), 2);
+        assert_eq!(white_count(&clamped), 1);
+    }
+
+    #[test]
+    fn bottom_row_diffuses() {
+        let mut buf = RgbImage::from_pixel(64, 1, Rgb([100, 100, 100]));
+        dither(&mut buf, Boundary::Drop);
+        assert!(white_count(&buf) > 0);
+    }
+
+    #[test]
+    fn variable_coefficients() {
+        assert!(OSTROMOUKHOV.iter().all(|&[a, b, c, sum]| a + b + c == sum));
+
+        let dither_variable = |variable| {
+            let mut buf = RgbImage::from_pixel(32, 32, Rgb([64, 64, 64]));
+            let options = DiffusionOptions {
+                variable: Some(variable),
+                ..DiffusionOptions::default()
+            };
+            diffuse(&mut buf, &Palette::monochrome(), ColourSpace::RGB, &options);
+            buf
+        };
+        for variable in [Variable::Ostromoukhov, Variable::ZhouFang { seed: 1 }] {
+            let white = white_count(&dither_variable(variable));
+            assert!((240..=270).contains(&white), "{variable:?} {white}");
+        }
+        assert_eq!(
+            dither_variable(Variable::ZhouFang { seed: 1 }),
+            dither_variable(Variable::ZhouFang { seed: 1 })
+        );
+        assert_ne!(
+            dither_variable(Variable::ZhouFang { seed: 1 }),
+            dither_variable(Variable::ZhouFang { seed: 2 })
+        );
+    }
+
+    #[test]
+    fn wavefront_tiles() {
+        let source = gradient(50, 40);
+        let palette = four_colours();
+        let options = DiffusionOptions::default();
+        let mut sequential = source.clone();
+        diffuse(&mut sequential, &palette, ColourSpace::RGB, &options);
+        // a single tile is the same as diffusing the whole image
+        let mut whole = source.clone();
+        diffuse_wavefront(&mut whole, &palette, ColourSpace::RGB, &options, 64);
+        assert_eq!(whole, sequential);
+
+        // small tiles still keep the average brightness
+        let mut grey = RgbImage::from_pixel(40, 40, Rgb([64, 64, 64]));
+        diffuse_wavefront(
+            &mut grey,
+            &Palette::monochrome(),
+            ColourSpace::RGB,
+            &options,
+            8,
+        );
+        let white = white_count(&grey);
+        assert!((380..=420).contains(&white), "{white}");
+    }
+
+    #[test]
+    fn wrap_keeps_error() {
+        let mut buf = RgbImage::from_pixel(16, 16, Rgb([64, 64, 64]));
+        dither(&mut buf, Boundary::Wrap);
+        assert_eq!(white_count(&buf), 64);
+
+        // narrow images push most of their error along the row past its end
+        for (width, expected) in [(1, 16), (3, 48), (7, 112)] {
+            let mut buf = RgbImage::from_pixel(width, 64, Rgb([64, 64, 64]));
+            let options = DiffusionOptions {
+                boundary: Boundary::Wrap,
+                allow_overshoot: true,
+                ..DiffusionOptions::default()
+            };
+            diffuse(&mut buf, &Palette::monochrome(), ColourSpace::RGB, &options);
+            assert_eq!(white_count(&buf), expected, "{width}");
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Direct colour quantisation, reducing each channel to a fixed number of bits rather than
+//! matching against a [`crate::Palette`]. This is what RGB565 and similar framebuffers need.
+//!
+//! Levels are picked from 8-bit channel values, so these only take an `RgbImage`.
+
+use crate::diffusion::{DiffusionOptions, diffuse_with};
+use crate::f32_to_u8;
+use crate::float::round;
+use crate::parallel::for_each_position;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use image::RgbImage;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Bits kept for each colour channel, between 1 and 8.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChannelDepth {
+    red: u8,
+    green: u8,
+    blue: u8,
+}
+
+impl ChannelDepth {
+    pub const RGB565: Self = Self::new(5, 6, 5);
+    pub const RGB444: Self = Self::new(4, 4, 4);
+    pub const RGB332: Self = Self::new(3, 3, 2);
+
+    /// Creates a new channel depth, clamping each channel to between 1 and 8 bits.
+    #[must_use]
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        const fn clamp(bits: u8) -> u8 {
+            if bits < 1 {
+                1
+            } else if bits > 8 {
+                8
+            } else {
+                bits
+            }
+        }
+        Self {
+            red: clamp(red),
+            green: clamp(green),
+            blue: clamp(blue),
+        }
+    }
+
+    /// Returns the bits kept for the red, green and blue channels.
+    #[must_use]
+    pub const fn bits(self) -> [u8; 3] {
+        [self.red, self.green, self.blue]
+    }
+}
+
+fn max_level(bits: u8) -> f32 {
+    f32::from((1u16 << bits) - 1)
+}
+
+/// Returns the nearest `bits`-bit level to an 8-bit value.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+pub(crate) fn to_level(value: u8, bits: u8) -> u8 {
+    round(f32::from(value) * max_level(bits) / 255.0) as u8
+}
+
+/// Expands a `bits`-bit level back to the full 8-bit range.
+fn from_level(level: u8, bits: u8) -> u8 {
+    f32_to_u8(f32::from(level) * 255.0 / max_level(bits))
+}
+
+fn quantise_value(value: f32, bits: u8) -> u8 {
+    from_level(to_level(f32_to_u8(value), bits), bits)
+}
+
+/// Reduces each channel of an image to the nearest value representable with the given depth.
+///
+/// With the `rayon` feature, pixels are quantised in parallel.
+#[cfg(feature = "std")]
+pub fn quantise_channels(buf: &mut RgbImage, depth: ChannelDepth) {
+    quantise_channels_pixels(buf.as_chunks_mut().0, depth);
+}
+
+/// Like [`quantise_channels`], for pixels in any order.
+pub fn quantise_channels_pixels(pixels: &mut [[u8; 3]], depth: ChannelDepth) {
+    let bits = depth.bits();
+    let quantise = |pixel: &mut [u8; 3]| {
+        for (c, &b) in pixel.iter_mut().zip(&bits) {
+            *c = quantise_value(f32::from(*c), b);
+        }
+    };
+    #[cfg(feature = "rayon")]
+    pixels.par_iter_mut().for_each(quantise);
+    #[cfg(not(feature = "rayon"))]
+    pixels.iter_mut().for_each(quantise);
+}
+
+/// Reduces each channel of an image to the given depth, applying Floyd–Steinberg dithering to
+/// each channel independently.
+#[cfg(feature = "std")]
+pub fn quantise_and_dither_channels(buf: &mut RgbImage, depth: ChannelDepth) {
+    let (width, height) = buf.dimensions();
+    quantise_and_dither_channels_pixels(buf.as_chunks_mut().0, width, height, depth);
+}
+
+/// Like [`quantise_and_dither_channels`], for a `width` by `height` image stored as row-major
+/// pixels.
+pub fn quantise_and_dither_channels_pixels(
+    pixels: &mut [[u8; 3]],
+    width: u32,
+    height: u32,
+    depth: ChannelDepth,
+) {
+    let bits = depth.bits();
+    let mut work: Vec<[f32; 3]> = pixels.iter().map(|p| p.map(f32::from)).collect();
+    diffuse_with(
+        &mut work,
+        width,
+        height,
+        &DiffusionOptions::default(),
+        |x, y, pixel| {
+            let new: [u8; 3] = core::array::from_fn(|c| quantise_value(pixel[c], bits[c]));
+            pixels[y as usize * width as usize + x as usize] = new;
+            new.map(f32::from)
+        },
+    );
+}
+
+/// Returns the threshold in [0, 1) of a `2^order` by `2^order` Bayer matrix at a position.
+///
+/// `order` is clamped to between 1 and 8, as larger matrices overflow and gain nothing.
+#[allow(clippy::cast_precision_loss)]
+pub(crate) fn bayer_threshold(x: u32, y: u32, order: u32) -> f32 {
+    let order = order.clamp(1, 8);
+    let mut value = 0u32;
+    for bit in 0..order {
+        let xb = (x >> bit) & 1;
+        let yb = (y >> bit) & 1;
+        value |= ((xb ^ yb) << 1 | yb) << (2 * (order - 1 - bit));
+    }
+    value as f32 / (1u32 << (2 * order)) as f32
+}
+
+/// Reduces each channel of an image to the given depth using ordered dithering with a
+/// `2^order` by `2^order` Bayer matrix. `order` is clamped to between 1 and 8.
+///
+/// With the `rayon` feature, pixels are dithered in parallel.
+#[cfg(feature = "std")]
+pub fn ordered_dither_channels(buf: &mut RgbImage, depth: ChannelDepth, order: u32) {
+    let width = buf.width();
+    ordered_dither_channels_pixels(buf.as_chunks_mut().0, width, depth, order);
+}
+
+/// Like [`ordered_dither_channels`], for an image `width` pixels wide stored as row-major
+/// pixels.
+pub fn ordered_dither_channels_pixels(
+    pixels: &mut [[u8; 3]],
+    width: u32,
+    depth: ChannelDepth,
+    order: u32,
+) {
+    let bits = depth.bits();
+    for_each_position(pixels, width, |x, y, pixel| {
+        let offset = bayer_threshold(x, y, order) - 0.5;
+        for (c, &b) in pixel.iter_mut().zip(&bits) {
+            let step = 255.0 / max_level(b);
+            *c = quantise_value(f32::from(*c) + offset * step, b);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn levels_round_trip() {
+        for bits in 1..=8 {
+            for level in 0..(1u16 << bits) {
+                let level = u8::try_from(level).unwrap();
+                assert_eq!(to_level(from_level(level, bits), bits), level);
+            }
+        }
+    }
+
+    #[test]
+    fn bayer_2x2() {
+        let m: Vec<f32> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .map(|&(x, y)| bayer_threshold(x, y, 1) * 4.0)
+            .collect();
+        assert_eq!(m, vec![0.0, 2.0, 3.0, 1.0]);
+    }
+
+    #[test]
+    fn clamps_order() {
+        let threshold = |order| bayer_threshold(3, 5, order);
+        assert_eq!(threshold(0), threshold(1));
+        assert_eq!(threshold(16), threshold(8));
+        let mut pixels = vec![[100; 3]; 4 * 4];
+        ordered_dither_channels_pixels(&mut pixels, 4, ChannelDepth::RGB565, 16);
+        assert_eq!(ChannelDepth::new(0, 9, 5).bits(), [1, 8, 5]);
+    }
+
+    #[test]
+    fn dithering_preserves_average() {
+        let mut pixels = vec![[100; 3]; 16 * 16];
+        quantise_and_dither_channels_pixels(&mut pixels, 16, 16, ChannelDepth::new(1, 1, 1));
+        let white = pixels.iter().filter(|p| p[0] == 255).count();
+        assert!((90..=110).contains(&white), "{white}");
+    }
+}
@@ -0,0 +1,217 @@
+//! Knuth's dot diffusion.
+//!
+//! <https://doi.org/10.1145/35039.35040>
+//!
+//! The image is tiled with a class matrix, and pixels are quantised class by class. Each pixel's
+//! error goes to its neighbours of a higher class, which haven't been quantised yet. Pixels of
+//! the same class never neighbour each other, so the pixels of a large class are quantised in
+//! parallel.
+
+#[cfg(feature = "std")]
+use crate::DitherPixel;
+use crate::diffusion::ErrorSpace;
+use crate::parallel::parallel_map;
+#[cfg(feature = "std")]
+use crate::pixel::{row_major, write_indices};
+use crate::{ColourSpace, Palette};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use image::GenericImage;
+
+/// Knuth's 8x8 class matrix.
+const CLASS_MATRIX: [[u8; 8]; 8] = [
+    [34, 48, 40, 32, 29, 15, 23, 31],
+    [42, 58, 56, 53, 21, 5, 7, 10],
+    [50, 62, 61, 45, 13, 1, 2, 18],
+    [38, 46, 54, 37, 25, 17, 9, 26],
+    [28, 14, 22, 30, 35, 49, 41, 33],
+    [20, 4, 6, 11, 43, 59, 57, 52],
+    [12, 0, 3, 19, 51, 63, 60, 44],
+    [24, 16, 8, 27, 39, 47, 55, 36],
+];
+
+/// Fewest pixels in a class worth splitting across threads.
+const MIN_PARALLEL: usize = 4096;
+
+/// Offsets and weights of the neighbours error is diffused to.
+const NEIGHBOURS: [(i64, i64, f32); 8] = [
+    (-1, -1, 1.0),
+    (0, -1, 2.0),
+    (1, -1, 1.0),
+    (-1, 0, 2.0),
+    (1, 0, 2.0),
+    (-1, 1, 1.0),
+    (0, 1, 2.0),
+    (1, 1, 1.0),
+];
+
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn class(x: i64, y: i64) -> u8 {
+    CLASS_MATRIX[(y % 8) as usize][(x % 8) as usize]
+}
+
+/// Quantises an image using the given palette and colour space with dot diffusion, measuring
+/// error in `error_space`.
+#[cfg(feature = "std")]
+pub fn dot_diffuse<I>(buf: &mut I, palette: &Palette, space: ColourSpace, error_space: ErrorSpace)
+where
+    I: GenericImage,
+    I::Pixel: DitherPixel,
+{
+    let (width, height) = buf.dimensions();
+    let source: Vec<[f32; 3]> = row_major(buf).map(|p| p.to_srgb()).collect();
+    let indices = dot_diffuse_indices(&source, width, height, palette, space, error_space);
+    write_indices(buf, indices, palette);
+}
+
+/// Like [`dot_diffuse`], for a `width` by `height` image stored as row-major pixels.
+pub fn dot_diffuse_pixels(
+    pixels: &mut [[u8; 3]],
+    width: u32,
+    height: u32,
+    palette: &Palette,
+    space: ColourSpace,
+    error_space: ErrorSpace,
+) {
+    let source: Vec<[f32; 3]> = pixels.iter().map(|p| p.map(f32::from)).collect();
+    let indices = dot_diffuse_indices(&source, width, height, palette, space, error_space);
+    for (pixel, idx) in pixels.iter_mut().zip(indices) {
+        *pixel = palette.get_output_colours()[idx].0;
+    }
+}
+
+/// Dithers row-major sRGB pixels, returning the palette index of each.
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn dot_diffuse_indices(
+    source: &[[f32; 3]],
+    width: u32,
+    height: u32,
+    palette: &Palette,
+    space: ColourSpace,
+    error_space: ErrorSpace,
+) -> Vec<usize> {
+    let (w, h) = (i64::from(width), i64::from(height));
+    let colours = error_space.palette_coordinates(palette);
+    let source: Vec<[f32; 3]> = source.iter().map(|&p| error_space.to_space(p)).collect();
+    let neighbours = |x: i64, y: i64| {
+        NEIGHBOURS.iter().filter_map(move |&(dx, dy, weight)| {
+            let (nx, ny) = (x + dx, y + dy);
+            ((0..w).contains(&nx) && (0..h).contains(&ny)).then_some((nx, ny, weight))
+        })
+    };
+
+    // the positions of each class, found once rather than on every pass
+    let mut classes: Vec<Vec<(i64, i64)>> = vec![Vec::new(); 64];
+    for y in 0..h {
+        for x in 0..w {
+            classes[usize::from(class(x, y))].push((x, y));
+        }
+    }
+
+    // each pixel's error, already divided by the total weight of the neighbours it goes to
+    let mut errors = vec![[0.0f32; 3]; source.len()];
+    let mut indices = vec![0usize; source.len()];
+
+    for (k, members) in (0..).zip(&classes) {
+        // every pixel is independent within a class
+        let step = |j: usize| {
+            let (x, y) = members[j];
+            let i = (y * w + x) as usize;
+            let mut value = source[i];
+            for (nx, ny, weight) in neighbours(x, y) {
+                if class(nx, ny) < k {
+                    let err = errors[(ny * w + nx) as usize];
+                    for (v, e) in value.iter_mut().zip(err) {
+                        *v += e * weight;
+                    }
+                }
+            }
+            let value = error_space.clamp(value);
+            let idx = palette.closest_idx_f32(space, error_space.to_srgb(value));
+
+            let total: f32 = neighbours(x, y)
+                .filter(|&(nx, ny, _)| class(nx, ny) > k)
+                .map(|(_, _, weight)| weight)
+                .sum();
+            let err = if total > 0.0 {
+                core::array::from_fn(|c| (value[c] - colours[idx][c]) / total)
+            } else {
+                [0.0; 3]
+            };
+            (i, idx, err)
+        };
+        let results = if members.len() >= MIN_PARALLEL {
+            parallel_map(members.len(), step)
+        } else {
+            (0..members.len()).map(step).collect()
+        };
+        for (i, idx, err) in results {
+            indices[i] = idx;
+            errors[i] = err;
+        }
+    }
+
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::fixtures::{four_colours, gradient};
+    #[cfg(feature = "std")]
+    use crate::quantise_image;
+
+    #[test]
+    fn dithers_mid_grey() {
+        let mut classes: Vec<u8> = CLASS_MATRIX.iter().flatten().copied().collect();
+        classes.sort_unstable();
+        assert!(classes.iter().copied().eq(0..64));
+
+        let mut pixels = vec![[64; 3]; 37 * 29];
+        dot_diffuse_pixels(
+            &mut pixels,
+            37,
+            29,
+            &Palette::monochrome(),
+            ColourSpace::RGB,
+            ErrorSpace::Srgb,
+        );
+        let white = pixels.iter().filter(|p| p[0] == 255).count();
+        // a quarter of 1073 pixels, allowing for error lost at local maxima of the class matrix
+        assert!((220..=290).contains(&white), "{white}");
+
+        // large enough for each class to be split across threads
+        let mut pixels = vec![[64; 3]; 512 * 512];
+        dot_diffuse_pixels(
+            &mut pixels,
+            512,
+            512,
+            &Palette::monochrome(),
+            ColourSpace::RGB,
+            ErrorSpace::Srgb,
+        );
+        let white = pixels.iter().filter(|p| p[0] == 255).count();
+        assert!((55_000..=70_000).contains(&white), "{white}");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn first_class_gets_no_error() {
+        // so it's quantised as it is
+        let source = gradient(37, 29);
+        let palette = four_colours();
+        let mut buf = source.clone();
+        dot_diffuse(&mut buf, &palette, ColourSpace::CIELAB, ErrorSpace::Srgb);
+        let mut quantised = source;
+        quantise_image(&mut quantised, &palette, ColourSpace::CIELAB);
+        for (x, y, p) in buf.enumerate_pixels() {
+            if class(i64::from(x), i64::from(y)) == 0 {
+                assert_eq!(*p, quantised[(x, y)], "({x}, {y})");
+            }
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//! Images and palettes shared by tests.
+
+use crate::Palette;
+use image::{Rgb, RgbImage};
+
+/// A gradient with red increasing across the image and green increasing down it.
+pub(crate) fn gradient(width: u32, height: u32) -> RgbImage {
+    let ramp = |i: u32, len: u32| u8::try_from(i * 255 / (len - 1).max(1)).unwrap();
+    RgbImage::from_fn(width, height, |x, y| {
+        Rgb([ramp(x, width), ramp(y, height), 90])
+    })
+}
+
+/// Black, white, red and blue.
+pub(crate) fn four_colours() -> Palette {
+    Palette::from([[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 0, 255]].as_slice())
+}
@@ -0,0 +1,75 @@
+//! Float functions that need `std`, falling back to `libm` without it.
+
+// only colour space conversions are available without `alloc`
+#![cfg_attr(not(feature = "alloc"), allow(dead_code))]
+
+#[cfg(feature = "std")]
+pub(crate) fn powf(x: f32, y: f32) -> f32 {
+    x.powf(y)
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn powf(x: f32, y: f32) -> f32 {
+    libm::powf(x, y)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn cbrt(x: f32) -> f32 {
+    x.cbrt()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn cbrt(x: f32) -> f32 {
+    libm::cbrtf(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn sqrt(x: f32) -> f32 {
+    x.sqrt()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn sqrt(x: f32) -> f32 {
+    libm::sqrtf(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn round(x: f32) -> f32 {
+    x.round()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn round(x: f32) -> f32 {
+    libm::roundf(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn powi(x: f32, n: i32) -> f32 {
+    x.powi(n)
+}
+
+#[cfg(not(feature = "std"))]
+#[allow(clippy::cast_precision_loss)]
+pub(crate) fn powi(x: f32, n: i32) -> f32 {
+    libm::powf(x, n as f32)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn exp(x: f32) -> f32 {
+    x.exp()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn exp(x: f32) -> f32 {
+    libm::expf(x)
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn ceil(x: f32) -> f32 {
+    x.ceil()
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn ceil(x: f32) -> f32 {
+    libm::ceilf(x)
+}
@@ -0,0 +1,37 @@
+//! Writers for image and framebuffer formats consumed by other tools and devices.
+//!
+//! To emit an image in a panel's native scan order, dither it in its logical orientation and
+//! transform the result with [`crate::orientation::Orientation::apply_image`] before encoding.
+
+#[cfg(feature = "std")]
+pub mod bmp;
+pub mod mono;
+#[cfg(feature = "std")]
+pub mod netpbm;
+pub mod planes;
+pub mod rgb;
+pub mod source;
+
+#[cfg(feature = "std")]
+pub(crate) use crate::space::luma;
+use anyhow::{Result, anyhow};
+
+/// Checks that a name can be used as a C and Rust identifier.
+pub(crate) fn check_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("'{name}' is not a valid identifier"))
+    }
+}
+
+/// Returns which colour of a two colour palette is darker, and so drawn as ink by 1-bit formats.
+#[cfg(feature = "std")]
+pub(crate) fn ink_idx(colours: &[crate::Rgb<u8>]) -> u8 {
+    u8::from(luma(colours[0]) > luma(colours[1]))
+}
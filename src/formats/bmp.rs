@@ -0,0 +1,175 @@
+//! Palettised BMP writer.
+//!
+//! <https://en.wikipedia.org/wiki/BMP_file_format>
+
+use crate::{DitherPixel, Palette, image_to_indices};
+use anyhow::{Context, Result, anyhow};
+use image::GenericImageView;
+use std::io::Write;
+
+const FILE_HEADER_LEN: u32 = 14;
+const INFO_HEADER_LEN: u32 = 40;
+// 72 DPI
+const PIXELS_PER_METRE: u32 = 2835;
+
+/// Bits used to store each palette index.
+#[derive(Copy, Clone, Debug)]
+pub enum BitDepth {
+    One,
+    Four,
+    Eight,
+}
+
+impl BitDepth {
+    /// Returns the smallest bit depth that can index every colour in the palette.
+    #[must_use]
+    pub fn for_palette(palette: &Palette) -> Self {
+        match palette.get_colours().len() {
+            0..=2 => Self::One,
+            3..=16 => Self::Four,
+            _ => Self::Eight,
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            Self::One => 1,
+            Self::Four => 4,
+            Self::Eight => 8,
+        }
+    }
+}
+
+/// Writes a quantised image as an indexed BMP with the palette as its colour table.
+///
+/// Pixels that aren't palette colours are written as index 0.
+///
+/// # Errors
+///
+/// Returns an error if the palette has more colours than the bit depth can index, the image is
+/// empty or too large, or writing fails.
+pub fn write_bmp<W: Write, I>(mut w: W, buf: &I, palette: &Palette, depth: BitDepth) -> Result<()>
+where
+    I: GenericImageView,
+    I::Pixel: DitherPixel,
+{
+    let bits = depth.bits();
+    let colours = palette.get_output_colours();
+    if colours.len() > 1 << bits {
+        return Err(anyhow!(
+            "{} bit BMPs can have at most {} colours, got: {}",
+            bits,
+            1 << bits,
+            colours.len()
+        ));
+    }
+
+    let (width, height) = buf.dimensions();
+    if width == 0 || height == 0 {
+        return Err(anyhow!("BMPs can't be empty, got: {width}x{height}"));
+    }
+    // rows are padded to a multiple of 4 bytes
+    let row_len = (width * bits).div_ceil(32) * 4;
+    let table_len = 4 * (1 << bits);
+    let offset = FILE_HEADER_LEN + INFO_HEADER_LEN + table_len;
+    let image_len = row_len
+        .checked_mul(height)
+        .context("Image too large for BMP")?;
+    let file_len = offset
+        .checked_add(image_len)
+        .context("Image too large for BMP")?;
+
+    w.write_all(b"BM")?;
+    w.write_all(&file_len.to_le_bytes())?;
+    w.write_all(&[0; 4])?;
+    w.write_all(&offset.to_le_bytes())?;
+
+    w.write_all(&INFO_HEADER_LEN.to_le_bytes())?;
+    w.write_all(&i32::try_from(width)?.to_le_bytes())?;
+    w.write_all(&i32::try_from(height)?.to_le_bytes())?;
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&u16::try_from(bits)?.to_le_bytes())?;
+    w.write_all(&0u32.to_le_bytes())?; // BI_RGB, uncompressed
+    w.write_all(&image_len.to_le_bytes())?;
+    w.write_all(&PIXELS_PER_METRE.to_le_bytes())?;
+    w.write_all(&PIXELS_PER_METRE.to_le_bytes())?;
+    w.write_all(&u32::try_from(colours.len())?.to_le_bytes())?;
+    w.write_all(&0u32.to_le_bytes())?;
+
+    for i in 0..1 << bits {
+        let [r, g, b] = colours.get(i).map_or([0; 3], |c| c.0);
+        w.write_all(&[b, g, r, 0])?;
+    }
+
+    let indices = image_to_indices(buf, palette);
+    let per_byte = (8 / bits) as usize;
+    let mut row = vec![0; row_len as usize];
+    // rows are stored bottom to top
+    for line in indices.chunks(width as usize).rev() {
+        row.fill(0);
+        for (byte, idxs) in row.iter_mut().zip(line.chunks(per_byte)) {
+            *byte = idxs.iter().enumerate().fold(0, |acc, (i, &idx)| {
+                acc | idx << (8 - bits as usize * (i + 1))
+            });
+        }
+        w.write_all(&row)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn headers_and_rows() {
+        let palette = Palette::from([[0, 0, 0], [255, 255, 255], [255, 0, 0]].as_slice());
+        let colours = palette.get_output_colours();
+        // 3x2, top row 0 1 2, bottom row 2 2 1
+        let buf = RgbImage::from_fn(3, 2, |x, y| {
+            colours[[[0, 1, 2], [2, 2, 1]][y as usize][x as usize]]
+        });
+        let mut out = Vec::new();
+        write_bmp(&mut out, &buf, &palette, BitDepth::Four).unwrap();
+
+        let offset = 14 + 40 + 4 * 16;
+        assert_eq!(&out[..2], b"BM");
+        assert_eq!(u32_at(&out, 2) as usize, out.len());
+        assert_eq!(u32_at(&out, 10), offset);
+        assert_eq!((u32_at(&out, 18), u32_at(&out, 22)), (3, 2));
+        assert_eq!(u16::from_le_bytes([out[28], out[29]]), 4);
+        assert_eq!(u32_at(&out, 34), 8);
+        assert_eq!(u32_at(&out, 46), 3);
+        // colour table is BGRX
+        assert_eq!(&out[54 + 8..54 + 12], [0, 0, 255, 0]);
+        // 12 bits of pixels padded to 4 bytes per row, bottom row first
+        let offset = offset as usize;
+        assert_eq!(&out[offset..], [0x22, 0x10, 0, 0, 0x01, 0x20, 0, 0]);
+    }
+
+    #[test]
+    fn pads_one_bit_rows() {
+        let buf = RgbImage::from_fn(33, 1, |x, _| Rgb([if x == 32 { 255 } else { 0 }; 3]));
+        let mut out = Vec::new();
+        write_bmp(&mut out, &buf, &Palette::monochrome(), BitDepth::One).unwrap();
+        let pixels = &out[14 + 40 + 8..];
+        assert_eq!(pixels, [0, 0, 0, 0, 0x80, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let palette = Palette::monochrome();
+        for (w, h) in [(0, 3), (3, 0)] {
+            let buf = RgbImage::new(w, h);
+            assert!(write_bmp(Vec::new(), &buf, &palette, BitDepth::One).is_err());
+        }
+        let palette = Palette::from([[0, 0, 0], [1, 1, 1], [2, 2, 2]].as_slice());
+        assert!(write_bmp(Vec::new(), &RgbImage::new(1, 1), &palette, BitDepth::One).is_err());
+    }
+}
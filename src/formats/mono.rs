@@ -0,0 +1,217 @@
+//! 1-bit outputs for monochrome displays and formats.
+//!
+//! These take an image quantised to a two colour palette (e.g. [`Palette::monochrome`]). XBM
+//! draws set bits as foreground, so the darker palette colour is written as set bits, as
+//! [`crate::formats::netpbm::write_pbm`] does. The framebuffer layouts are for displays that light
+//! set bits, so pixels of the second palette colour are written as set bits, and pixels of the
+//! first as clear bits.
+
+use crate::formats::check_name;
+#[cfg(feature = "std")]
+use crate::formats::ink_idx;
+#[cfg(feature = "std")]
+use crate::{DitherPixel, Palette, image_to_indices};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use anyhow::{Result, anyhow};
+use core::fmt::Write;
+#[cfg(feature = "std")]
+use image::GenericImageView;
+
+#[cfg(feature = "std")]
+fn to_indices<I>(buf: &I, palette: &Palette) -> Result<Vec<u8>>
+where
+    I: GenericImageView,
+    I::Pixel: DitherPixel,
+{
+    let len = palette.get_colours().len();
+    if len != 2 {
+        return Err(anyhow!("Expected a 2 colour palette, got: {len}"));
+    }
+    Ok(image_to_indices(buf, palette))
+}
+
+/// Packs each row into bytes, padding the end of each row to a whole byte. An image with no
+/// columns packs to nothing.
+fn pack_rows(indices: &[u8], width: u32, msb_first: bool) -> Vec<u8> {
+    if width == 0 {
+        return Vec::new();
+    }
+    indices
+        .chunks(width as usize)
+        .flat_map(|row| row.chunks(8))
+        .map(|byte| {
+            byte.iter().enumerate().fold(0, |acc, (i, &idx)| {
+                let shift = if msb_first { 7 - i } else { i };
+                acc | (u8::from(idx != 0) << shift)
+            })
+        })
+        .collect()
+}
+
+/// Creates an XBM image, which is C source declaring `<name>_width`, `<name>_height` and
+/// `<name>_bits`. The darker palette colour is the foreground.
+///
+/// # Errors
+///
+/// Returns an error if `name` isn't a valid identifier or the palette doesn't have 2 colours.
+#[cfg(feature = "std")]
+pub fn to_xbm<I>(name: &str, buf: &I, palette: &Palette) -> Result<String>
+where
+    I: GenericImageView,
+    I::Pixel: DitherPixel,
+{
+    let indices = to_indices(buf, palette)?;
+    let ink = ink_idx(palette.get_output_colours());
+    let bits: Vec<u8> = indices.iter().map(|&idx| u8::from(idx == ink)).collect();
+    indices_to_xbm(name, &bits, buf.dimensions())
+}
+
+/// Like [`to_xbm`], for palette indices in row-major order. Index 0 is a clear bit (background)
+/// and any other index a set bit (foreground).
+///
+/// # Errors
+///
+/// Returns an error if `name` isn't a valid identifier or there isn't exactly one index per
+/// pixel.
+pub fn indices_to_xbm(name: &str, indices: &[u8], (width, height): (u32, u32)) -> Result<String> {
+    check_name(name)?;
+    if indices.len() != width as usize * height as usize {
+        return Err(anyhow!(
+            "Expected {} indices for {width}x{height}, got: {}",
+            width as usize * height as usize,
+            indices.len()
+        ));
+    }
+    let bytes = pack_rows(indices, width, false);
+
+    let mut out = String::new();
+    writeln!(out, "#define {name}_width {width}").unwrap();
+    writeln!(out, "#define {name}_height {height}").unwrap();
+    writeln!(out, "static unsigned char {name}_bits[] = {{").unwrap();
+    for line in bytes.chunks(12) {
+        let values: Vec<String> = line.iter().map(|b| format!("0x{b:02x}")).collect();
+        writeln!(out, "    {},", values.join(", ")).unwrap();
+    }
+    writeln!(out, "}};").unwrap();
+
+    Ok(out)
+}
+
+/// Packs the image as a raw bitplane: rows top to bottom, 8 horizontal pixels per byte with the
+/// leftmost pixel in the most significant bit, and each row padded to a whole byte.
+///
+/// # Errors
+///
+/// Returns an error if the palette doesn't have 2 colours.
+#[cfg(feature = "std")]
+pub fn to_bitplane<I>(buf: &I, palette: &Palette) -> Result<Vec<u8>>
+where
+    I: GenericImageView,
+    I::Pixel: DitherPixel,
+{
+    Ok(indices_to_bitplane(&to_indices(buf, palette)?, buf.width()))
+}
+
+/// Like [`to_bitplane`], for palette indices in row-major order for an image `width` pixels
+/// wide. Index 0 is a clear bit and any other index a set bit.
+#[must_use]
+pub fn indices_to_bitplane(indices: &[u8], width: u32) -> Vec<u8> {
+    pack_rows(indices, width, true)
+}
+
+/// Packs the image in the page layout used by SSD1306 and SH1106 OLED controllers.
+///
+/// Each byte holds 8 vertical pixels with the topmost pixel in the least significant bit. Bytes
+/// run left to right across a page of 8 rows, and pages run top to bottom. The last page is
+/// padded with clear bits if the height isn't a multiple of 8.
+///
+/// # Errors
+///
+/// Returns an error if the palette doesn't have 2 colours.
+#[cfg(feature = "std")]
+pub fn to_vertical_pages<I>(buf: &I, palette: &Palette) -> Result<Vec<u8>>
+where
+    I: GenericImageView,
+    I::Pixel: DitherPixel,
+{
+    indices_to_vertical_pages(&to_indices(buf, palette)?, buf.dimensions())
+}
+
+/// Like [`to_vertical_pages`], for palette indices in row-major order. Index 0 is a clear bit and
+/// any other index a set bit.
+///
+/// # Errors
+///
+/// Returns an error if there isn't exactly one index per pixel.
+pub fn indices_to_vertical_pages(indices: &[u8], (width, height): (u32, u32)) -> Result<Vec<u8>> {
+    if indices.len() != width as usize * height as usize {
+        return Err(anyhow!(
+            "Expected {} indices for {width}x{height}, got: {}",
+            width as usize * height as usize,
+            indices.len()
+        ));
+    }
+    let (width, height) = (width as usize, height as usize);
+
+    let mut res = Vec::with_capacity(width * height.div_ceil(8));
+    for page in 0..height.div_ceil(8) {
+        for x in 0..width {
+            let mut byte = 0;
+            for bit in 0..8 {
+                let y = page * 8 + bit;
+                if y < height && indices[y * width + x] != 0 {
+                    byte |= 1 << bit;
+                }
+            }
+            res.push(byte);
+        }
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn vertical_pages() {
+        // a diagonal line on a 2x10 image
+        let indices: Vec<u8> = (0..10)
+            .flat_map(|y| (0..2).map(move |x| u8::from(x == y || y == 9)))
+            .collect();
+        assert_eq!(
+            indices_to_vertical_pages(&indices, (2, 10)).unwrap(),
+            [0b01, 0b10, 0b10, 0b10]
+        );
+        assert!(indices_to_vertical_pages(&indices[..18], (2, 10)).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn xbm_is_lsb_first() {
+        let palette = Palette::monochrome();
+        let mut buf = RgbImage::new(9, 1);
+        buf.put_pixel(0, 0, Rgb([255, 255, 255]));
+        buf.put_pixel(8, 0, Rgb([255, 255, 255]));
+        let xbm = to_xbm("dot", &buf, &palette).unwrap();
+        assert!(xbm.contains("#define dot_width 9\n#define dot_height 1\n"));
+        // black is the foreground
+        assert!(xbm.contains("    0xfe, 0x00,\n"));
+        assert!(to_xbm("dot", &buf, &Palette::from([[0, 0, 0]].as_slice())).is_err());
+        assert!(indices_to_xbm("dot", &[0; 8], (9, 1)).is_err());
+    }
+
+    #[test]
+    fn empty_images() {
+        for (w, h) in [(0, 3), (3, 0), (0, 0)] {
+            assert!(indices_to_bitplane(&[], w).is_empty());
+            assert!(indices_to_vertical_pages(&[], (w, h)).unwrap().is_empty());
+            assert!(indices_to_xbm("empty", &[], (w, h)).is_ok());
+        }
+    }
+}
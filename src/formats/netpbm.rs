@@ -0,0 +1,240 @@
+//! Netpbm (PBM, PGM, PPM and PAM) writers.
+//!
+//! <https://netpbm.sourceforge.net/doc/#formats>
+//!
+//! PBMs are written from palette indices, so take any [`DitherPixel`] image. PGM, PPM and PAM
+//! hold 8-bit channel values, so those writers only take an [`RgbImage`].
+
+use crate::formats::{ink_idx, luma};
+use crate::{DitherPixel, Palette, image_to_indices};
+use anyhow::{Result, anyhow};
+use image::{GenericImageView, RgbImage};
+use std::io::Write;
+
+/// Whether to write the raw (binary) or plain (ASCII) variant of a format.
+#[derive(Copy, Clone, Debug)]
+pub enum Encoding {
+    Binary,
+    Ascii,
+}
+
+// The spec asks for plain format lines to be no longer than 70 characters.
+const MAX_LINE_LEN: usize = 70;
+
+fn write_ascii<W: Write>(w: &mut W, values: impl Iterator<Item = u8>, sep: &str) -> Result<()> {
+    let mut line = String::new();
+    for v in values {
+        let v = v.to_string();
+        if !line.is_empty() && line.len() + sep.len() + v.len() > MAX_LINE_LEN {
+            writeln!(w, "{line}")?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push_str(sep);
+        }
+        line.push_str(&v);
+    }
+    if !line.is_empty() {
+        writeln!(w, "{line}")?;
+    }
+    Ok(())
+}
+
+/// Writes a 1-bit PBM from an image quantised to a two colour palette.
+///
+/// The darker palette colour is written as black (1) and the lighter as white (0). Pixels that
+/// aren't palette colours are written as the first palette colour.
+///
+/// # Errors
+///
+/// Returns an error if the palette doesn't have exactly 2 colours or writing fails.
+pub fn write_pbm<W: Write, I>(
+    mut w: W,
+    buf: &I,
+    palette: &Palette,
+    encoding: Encoding,
+) -> Result<()>
+where
+    I: GenericImageView,
+    I::Pixel: DitherPixel,
+{
+    let colours = palette.get_output_colours();
+    if colours.len() != 2 {
+        return Err(anyhow!(
+            "PBM needs a 2 colour palette, got: {}",
+            colours.len()
+        ));
+    }
+    let ink = ink_idx(colours);
+    let (width, height) = buf.dimensions();
+    let indices = image_to_indices(buf, palette);
+
+    match encoding {
+        Encoding::Binary => {
+            write!(w, "P4\n{width} {height}\n")?;
+            for row in indices.chunks(width.max(1) as usize) {
+                let bits: Vec<bool> = row.iter().map(|&idx| idx == ink).collect();
+                let bytes: Vec<u8> = bits
+                    .chunks(8)
+                    .map(|c| {
+                        c.iter()
+                            .enumerate()
+                            .fold(0, |acc, (i, &b)| acc | (u8::from(b) << (7 - i)))
+                    })
+                    .collect();
+                w.write_all(&bytes)?;
+            }
+        }
+        Encoding::Ascii => {
+            write!(w, "P1\n{width} {height}\n")?;
+            write_ascii(&mut w, indices.iter().map(|&idx| u8::from(idx == ink)), "")?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes an 8-bit PGM using the luma of each pixel.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_pgm<W: Write>(mut w: W, buf: &RgbImage, encoding: Encoding) -> Result<()> {
+    let (width, height) = buf.dimensions();
+    let values = buf.pixels().map(|p| luma(*p));
+    match encoding {
+        Encoding::Binary => {
+            write!(w, "P5\n{width} {height}\n255\n")?;
+            w.write_all(&values.collect::<Vec<_>>())?;
+        }
+        Encoding::Ascii => {
+            write!(w, "P2\n{width} {height}\n255\n")?;
+            write_ascii(&mut w, values, " ")?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes an 8-bit per channel PPM.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_ppm<W: Write>(mut w: W, buf: &RgbImage, encoding: Encoding) -> Result<()> {
+    let (width, height) = buf.dimensions();
+    match encoding {
+        Encoding::Binary => {
+            write!(w, "P6\n{width} {height}\n255\n")?;
+            w.write_all(buf.as_raw())?;
+        }
+        Encoding::Ascii => {
+            write!(w, "P3\n{width} {height}\n255\n")?;
+            write_ascii(&mut w, buf.as_raw().iter().copied(), " ")?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes an RGB PAM. PAM has no plain variant, so this is always binary.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_pam<W: Write>(mut w: W, buf: &RgbImage) -> Result<()> {
+    let (width, height) = buf.dimensions();
+    write!(
+        w,
+        "P7\nWIDTH {width}\nHEIGHT {height}\nDEPTH 3\nMAXVAL 255\nTUPLTYPE RGB\nENDHDR\n"
+    )?;
+    w.write_all(buf.as_raw())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn checker() -> (RgbImage, Palette) {
+        let palette = Palette::from([[255, 255, 255], [0, 0, 0]].as_slice());
+        let buf = RgbImage::from_fn(10, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+        (buf, palette)
+    }
+
+    #[test]
+    fn pbm_binary_packs_rows_msb_first() {
+        let (buf, palette) = checker();
+        let mut out = Vec::new();
+        write_pbm(&mut out, &buf, &palette, Encoding::Binary).unwrap();
+        assert_eq!(out, b"P4\n10 2\n\xaa\x80\x55\x40");
+    }
+
+    #[test]
+    fn pbm_ascii() {
+        let (buf, palette) = checker();
+        let mut out = Vec::new();
+        write_pbm(&mut out, &buf, &palette, Encoding::Ascii).unwrap();
+        assert_eq!(out, b"P1\n10 2\n10101010100101010101\n");
+    }
+
+    fn two_pixels() -> RgbImage {
+        RgbImage::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgb([10, 20, 30])
+            } else {
+                Rgb([255, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn pgm_uses_luma() {
+        let buf = two_pixels();
+        let mut out = Vec::new();
+        write_pgm(&mut out, &buf, Encoding::Binary).unwrap();
+        assert_eq!(out, b"P5\n2 1\n255\n\x12\x4c");
+
+        let mut out = Vec::new();
+        write_pgm(&mut out, &buf, Encoding::Ascii).unwrap();
+        assert_eq!(out, b"P2\n2 1\n255\n18 76\n");
+    }
+
+    #[test]
+    fn ppm() {
+        let buf = two_pixels();
+        let mut out = Vec::new();
+        write_ppm(&mut out, &buf, Encoding::Binary).unwrap();
+        assert_eq!(out, b"P6\n2 1\n255\n\x0a\x14\x1e\xff\x00\x00");
+
+        let mut out = Vec::new();
+        write_ppm(&mut out, &buf, Encoding::Ascii).unwrap();
+        assert_eq!(out, b"P3\n2 1\n255\n10 20 30 255 0 0\n");
+    }
+
+    #[test]
+    fn ascii_wraps_long_lines() {
+        // 36 values of "255", and 18 of them would make a 71 character line
+        let buf = RgbImage::from_pixel(12, 1, Rgb([255; 3]));
+        let mut out = Vec::new();
+        write_ppm(&mut out, &buf, Encoding::Ascii).unwrap();
+        let line = vec!["255"; 17].join(" ");
+        let expected = format!("P3\n12 1\n255\n{line}\n{line}\n255 255\n");
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn pam() {
+        let mut out = Vec::new();
+        write_pam(&mut out, &two_pixels()).unwrap();
+        assert_eq!(
+            out,
+            b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\nTUPLTYPE RGB\nENDHDR\n\
+              \x0a\x14\x1e\xff\x00\x00"
+        );
+    }
+}
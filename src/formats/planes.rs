@@ -0,0 +1,132 @@
+//! Splits indexed images into 1-bit planes, as taken by tri-colour e-paper controllers which
+//! have a separate black and colour RAM.
+
+use crate::orientation::Orientation;
+#[cfg(feature = "std")]
+use crate::{DitherPixel, Palette, image_to_indices};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use image::GenericImageView;
+
+/// A single 1-bit plane.
+#[derive(Copy, Clone, Debug)]
+pub struct Plane<'a> {
+    /// Palette indices written as set bits.
+    pub indices: &'a [u8],
+    /// Flips every bit of the plane, for controllers where a set bit means "off".
+    pub invert: bool,
+}
+
+/// Order rows are written in.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum RowOrder {
+    #[default]
+    TopToBottom,
+    BottomToTop,
+}
+
+/// How an indexed image is split into planes.
+#[derive(Copy, Clone, Debug)]
+pub struct PlaneLayout<'a> {
+    pub planes: &'a [Plane<'a>],
+    pub row_order: RowOrder,
+    /// Transform from the image's orientation to the controller's, applied before packing.
+    pub orientation: Orientation,
+}
+
+/// Layout used by most black/white/red and black/white/yellow controllers, for a
+/// `[black, white, colour]` palette. The black plane is inverted so that black is a clear bit,
+/// and the colour plane has set bits for the colour.
+pub const TRI_COLOUR: PlaneLayout = PlaneLayout {
+    planes: &[
+        Plane {
+            indices: &[0],
+            invert: true,
+        },
+        Plane {
+            indices: &[2],
+            invert: false,
+        },
+    ],
+    row_order: RowOrder::TopToBottom,
+    orientation: Orientation::IDENTITY,
+};
+
+/// Packs palette indices, in row-major order for an image `width` pixels wide, into one buffer
+/// per plane. Each buffer has 8 pixels per byte with the leftmost pixel in the most significant
+/// bit, and each row padded to a whole byte.
+#[must_use]
+pub fn indices_to_planes(indices: &[u8], width: u32, layout: &PlaneLayout) -> Vec<Vec<u8>> {
+    if width == 0 || indices.is_empty() {
+        return vec![Vec::new(); layout.planes.len()];
+    }
+    let height = u32::try_from(indices.len() / width as usize).unwrap_or(u32::MAX);
+    let (indices, width, _) = layout.orientation.apply(indices, width, height);
+
+    let rows: Vec<&[u8]> = match layout.row_order {
+        RowOrder::TopToBottom => indices.chunks(width as usize).collect(),
+        RowOrder::BottomToTop => indices.chunks(width as usize).rev().collect(),
+    };
+
+    layout
+        .planes
+        .iter()
+        .map(|plane| {
+            rows.iter()
+                .flat_map(|row| row.chunks(8))
+                .map(|chunk| {
+                    let byte = chunk.iter().enumerate().fold(0u8, |acc, (i, idx)| {
+                        acc | u8::from(plane.indices.contains(idx)) << (7 - i)
+                    });
+                    if plane.invert {
+                        // only flip the bits that hold pixels, leaving row padding clear
+                        byte ^ (0xff << (8 - chunk.len()))
+                    } else {
+                        byte
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Splits a quantised image into 1-bit planes, see [`indices_to_planes`].
+#[must_use]
+#[cfg(feature = "std")]
+pub fn to_planes<I>(buf: &I, palette: &Palette, layout: &PlaneLayout) -> Vec<Vec<u8>>
+where
+    I: GenericImageView,
+    I::Pixel: DitherPixel,
+{
+    indices_to_planes(&image_to_indices(buf, palette), buf.width(), layout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orientation::Rotation;
+
+    #[test]
+    fn tri_colour_with_rotation_and_row_order() {
+        // 3x2 image:
+        // 0 1 2
+        // 2 1 0
+        let indices = [0, 1, 2, 2, 1, 0];
+        let planes = indices_to_planes(&indices, 3, &TRI_COLOUR);
+        assert_eq!(planes[0], vec![0b0110_0000, 0b1100_0000]);
+        assert_eq!(planes[1], vec![0b0010_0000, 0b1000_0000]);
+
+        // rotated clockwise it's 2x3:
+        // 2 0
+        // 1 1
+        // 0 2
+        let layout = PlaneLayout {
+            row_order: RowOrder::BottomToTop,
+            orientation: Orientation::rotated(Rotation::Clockwise90),
+            ..TRI_COLOUR
+        };
+        let planes = indices_to_planes(&indices, 3, &layout);
+        assert_eq!(planes[1], vec![0b0100_0000, 0, 0b1000_0000]);
+    }
+}
@@ -0,0 +1,110 @@
+//! Direct colour framebuffer encoders for displays that take RGB565, RGB444 or RGB332.
+//!
+//! Each channel is rounded to the nearest level, so images are best reduced first with one of
+//! the functions in [`crate::direct`] using the matching [`crate::direct::ChannelDepth`].
+
+use crate::direct::to_level;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use image::RgbImage;
+
+/// Byte order of multi-byte pixels.
+#[derive(Copy, Clone, Debug)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+fn pack([r, g, b]: [u8; 3], [r_bits, g_bits, b_bits]: [u8; 3]) -> u16 {
+    u16::from(to_level(r, r_bits)) << (g_bits + b_bits)
+        | u16::from(to_level(g, g_bits)) << b_bits
+        | u16::from(to_level(b, b_bits))
+}
+
+fn to_words(pixels: &[[u8; 3]], bits: [u8; 3], endian: Endian) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|&p| {
+            let word = pack(p, bits);
+            match endian {
+                Endian::Little => word.to_le_bytes(),
+                Endian::Big => word.to_be_bytes(),
+            }
+        })
+        .collect()
+}
+
+/// Encodes an image as 16-bit `RRRRRGGG GGGBBBBB` pixels.
+#[must_use]
+#[cfg(feature = "std")]
+pub fn to_rgb565(buf: &RgbImage, endian: Endian) -> Vec<u8> {
+    pixels_to_rgb565(buf.as_chunks().0, endian)
+}
+
+/// Like [`to_rgb565`], for pixels in row-major order.
+#[must_use]
+pub fn pixels_to_rgb565(pixels: &[[u8; 3]], endian: Endian) -> Vec<u8> {
+    to_words(pixels, [5, 6, 5], endian)
+}
+
+/// Encodes an image as 16-bit `0000RRRR GGGGBBBB` pixels.
+#[must_use]
+#[cfg(feature = "std")]
+pub fn to_rgb444(buf: &RgbImage, endian: Endian) -> Vec<u8> {
+    pixels_to_rgb444(buf.as_chunks().0, endian)
+}
+
+/// Like [`to_rgb444`], for pixels in row-major order.
+#[must_use]
+pub fn pixels_to_rgb444(pixels: &[[u8; 3]], endian: Endian) -> Vec<u8> {
+    to_words(pixels, [4, 4, 4], endian)
+}
+
+/// Encodes an image as 8-bit `RRRGGGBB` pixels.
+#[must_use]
+#[cfg(feature = "std")]
+pub fn to_rgb332(buf: &RgbImage) -> Vec<u8> {
+    pixels_to_rgb332(buf.as_chunks().0)
+}
+
+/// Like [`to_rgb332`], for pixels in row-major order.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn pixels_to_rgb332(pixels: &[[u8; 3]]) -> Vec<u8> {
+    pixels.iter().map(|&p| pack(p, [3, 3, 2]) as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_known_colours() {
+        let pixels = [[255, 0, 0], [0, 255, 255]];
+        assert_eq!(
+            pixels_to_rgb565(&pixels, Endian::Big),
+            [0xf8, 0x00, 0x07, 0xff]
+        );
+        assert_eq!(
+            pixels_to_rgb565(&pixels, Endian::Little),
+            [0x00, 0xf8, 0xff, 0x07]
+        );
+        assert_eq!(
+            pixels_to_rgb444(&pixels, Endian::Big),
+            [0x0f, 0x00, 0x00, 0xff]
+        );
+        assert_eq!(pixels_to_rgb332(&pixels), [0xe0, 0x1f]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn encodes_images() {
+        let buf = RgbImage::from_vec(2, 1, vec![255, 0, 0, 0, 255, 255]).unwrap();
+        assert_eq!(to_rgb565(&buf, Endian::Big), vec![0xf8, 0x00, 0x07, 0xff]);
+        assert_eq!(
+            to_rgb444(&buf, Endian::Little),
+            vec![0x00, 0x0f, 0xff, 0x00]
+        );
+        assert_eq!(to_rgb332(&buf), vec![0xe0, 0x1f]);
+    }
+}
@@ -0,0 +1,276 @@
+//! Exports packed images as C and Rust source code, for embedding framebuffers in firmware.
+
+use crate::Palette;
+use crate::formats::check_name;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use anyhow::{Result, anyhow};
+use core::fmt::Write;
+
+/// Words C reserves, which can't name the array. Rust names are upper case, so never clash.
+const C_KEYWORDS: [&str; 54] = [
+    "alignas",
+    "alignof",
+    "auto",
+    "bool",
+    "break",
+    "case",
+    "char",
+    "const",
+    "constexpr",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "nullptr",
+    "register",
+    "restrict",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "static_assert",
+    "struct",
+    "switch",
+    "thread_local",
+    "true",
+    "typedef",
+    "typeof",
+    "typeof_unqual",
+    "union",
+    "unsigned",
+    "void",
+    "volatile",
+    "while",
+    "_Alignas",
+    "_Alignof",
+    "_Atomic",
+    "_Bool",
+    "_Complex",
+    "_Generic",
+    "_Imaginary",
+    "_Noreturn",
+    "_Static_assert",
+];
+
+/// How each byte is written.
+#[derive(Copy, Clone, Debug)]
+pub enum Radix {
+    /// `0x1f`
+    Hex,
+    /// `31`
+    Decimal,
+}
+
+/// Options for source code exports.
+#[derive(Clone, Debug)]
+pub struct SourceOptions {
+    /// Number of bytes written per line.
+    pub values_per_line: usize,
+    pub radix: Radix,
+    /// Attribute placed after the C array declarator, e.g. `PROGMEM` or
+    /// `__attribute__((aligned(4)))`. Ignored for Rust.
+    pub attribute: Option<String>,
+    /// Emit the palette as an array rather than as a comment.
+    pub palette_array: bool,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        Self {
+            values_per_line: 16,
+            radix: Radix::Hex,
+            attribute: None,
+            palette_array: false,
+        }
+    }
+}
+
+fn format_byte(b: u8, radix: Radix) -> String {
+    match radix {
+        Radix::Hex => format!("0x{b:02x}"),
+        Radix::Decimal => b.to_string(),
+    }
+}
+
+fn write_values(out: &mut String, bytes: &[u8], options: &SourceOptions) {
+    for line in bytes.chunks(options.values_per_line.max(1)) {
+        let values: Vec<String> = line
+            .iter()
+            .map(|&b| format_byte(b, options.radix))
+            .collect();
+        writeln!(out, "    {},", values.join(", ")).unwrap();
+    }
+}
+
+fn palette_comment(out: &mut String, prefix: &str, palette: &Palette) {
+    writeln!(out, "{prefix} Palette:").unwrap();
+    for (i, c) in palette.get_output_colours().iter().enumerate() {
+        let [r, g, b] = c.0;
+        writeln!(out, "{prefix}   {i}: #{r:02x}{g:02x}{b:02x}").unwrap();
+    }
+}
+
+/// Creates a C header declaring the packed image bytes, with `<NAME>_WIDTH` and `<NAME>_HEIGHT`
+/// macros and the palette.
+///
+/// The bytes would normally come from [`crate::image_to_bytes`].
+///
+/// # Errors
+///
+/// Returns an error if `name` isn't a valid identifier or is a C keyword, or `bytes` is empty, as
+/// C arrays can't have no elements.
+pub fn to_c_header(
+    name: &str,
+    bytes: &[u8],
+    (width, height): (u32, u32),
+    palette: &Palette,
+    options: &SourceOptions,
+) -> Result<String> {
+    check_name(name)?;
+    if C_KEYWORDS.contains(&name) {
+        return Err(anyhow!("'{name}' is a C keyword"));
+    }
+    if bytes.is_empty() {
+        return Err(anyhow!("C arrays can't be empty"));
+    }
+    let upper = name.to_uppercase();
+    let attribute = options
+        .attribute
+        .as_ref()
+        .map_or(String::new(), |a| format!(" {a}"));
+    let mut out = String::new();
+
+    writeln!(out, "#ifndef {upper}_H").unwrap();
+    writeln!(out, "#define {upper}_H\n").unwrap();
+    writeln!(out, "#include <stdint.h>\n").unwrap();
+    writeln!(out, "#define {upper}_WIDTH {width}").unwrap();
+    writeln!(out, "#define {upper}_HEIGHT {height}\n").unwrap();
+
+    if options.palette_array {
+        let colours = palette.get_output_colours();
+        writeln!(
+            out,
+            "static const uint8_t {name}_palette[{}][3]{attribute} = {{",
+            colours.len()
+        )
+        .unwrap();
+        for c in colours {
+            let [r, g, b] = c.0.map(|v| format_byte(v, options.radix));
+            writeln!(out, "    {{{r}, {g}, {b}}},").unwrap();
+        }
+        writeln!(out, "}};\n").unwrap();
+    } else {
+        writeln!(out, "/*").unwrap();
+        palette_comment(&mut out, " *", palette);
+        writeln!(out, " */\n").unwrap();
+    }
+
+    writeln!(
+        out,
+        "static const uint8_t {name}[{}]{attribute} = {{",
+        bytes.len()
+    )
+    .unwrap();
+    write_values(&mut out, bytes, options);
+    writeln!(out, "}};\n").unwrap();
+    writeln!(out, "#endif // {upper}_H").unwrap();
+
+    Ok(out)
+}
+
+/// Creates Rust source declaring the packed image bytes as a `const` array, with `<NAME>_WIDTH`
+/// and `<NAME>_HEIGHT` constants and the palette. The result can be saved as a module or
+/// `include!`d, as the generated themes are.
+///
+/// The bytes would normally come from [`crate::image_to_bytes`].
+///
+/// # Errors
+///
+/// Returns an error if `name` isn't a valid identifier.
+pub fn to_rust_module(
+    name: &str,
+    bytes: &[u8],
+    (width, height): (u32, u32),
+    palette: &Palette,
+    options: &SourceOptions,
+) -> Result<String> {
+    check_name(name)?;
+    let upper = name.to_uppercase();
+    let mut out = String::new();
+
+    writeln!(out, "pub const {upper}_WIDTH: u32 = {width};").unwrap();
+    writeln!(out, "pub const {upper}_HEIGHT: u32 = {height};\n").unwrap();
+
+    if options.palette_array {
+        let colours = palette.get_output_colours();
+        writeln!(
+            out,
+            "pub const {upper}_PALETTE: [[u8; 3]; {}] = [",
+            colours.len()
+        )
+        .unwrap();
+        for c in colours {
+            let [r, g, b] = c.0.map(|v| format_byte(v, options.radix));
+            writeln!(out, "    [{r}, {g}, {b}],").unwrap();
+        }
+        writeln!(out, "];\n").unwrap();
+    } else {
+        palette_comment(&mut out, "//", palette);
+        writeln!(out).unwrap();
+    }
+
+    writeln!(out, "pub const {upper}: [u8; {}] = [", bytes.len()).unwrap();
+    write_values(&mut out, bytes, options);
+    writeln!(out, "];").unwrap();
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn c_header_with_attribute() {
+        let palette = Palette::from([[0, 0, 0], [255, 255, 255]].as_slice());
+        let options = SourceOptions {
+            values_per_line: 2,
+            attribute: Some("PROGMEM".to_string()),
+            palette_array: true,
+            ..SourceOptions::default()
+        };
+        let header = to_c_header("logo", &[1, 2, 255], (2, 3), &palette, &options).unwrap();
+        assert!(header.contains("#define LOGO_WIDTH 2\n#define LOGO_HEIGHT 3\n"));
+        assert!(header.contains("static const uint8_t logo_palette[2][3] PROGMEM = {"));
+        assert!(
+            header.contains(
+                "static const uint8_t logo[3] PROGMEM = {\n    0x01, 0x02,\n    0xff,\n};"
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_bad_names() {
+        let palette = Palette::from([[0, 0, 0]].as_slice());
+        let options = SourceOptions::default();
+        assert!(to_rust_module("1logo", &[], (0, 0), &palette, &options).is_err());
+        assert!(to_rust_module("my-logo", &[], (0, 0), &palette, &options).is_err());
+        assert!(to_c_header("int", &[0], (1, 1), &palette, &options).is_err());
+        assert!(to_c_header("logo", &[], (0, 0), &palette, &options).is_err());
+        assert!(to_rust_module("logo", &[], (0, 0), &palette, &options).is_ok());
+    }
+}
@@ -1,13 +1,74 @@
-#![doc = include_str!("../README.md")]
+#![cfg_attr(feature = "std", doc = include_str!("../README.md"))]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(all(feature = "alloc", not(feature = "std"), not(feature = "libm")))]
+compile_error!("The `alloc` feature needs either the `std` or the `libm` feature for float maths");
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod alpha;
+#[cfg(feature = "gif")]
+pub mod animation;
+#[cfg(feature = "std")]
+pub mod calibration;
+#[cfg(feature = "alloc")]
+pub mod dbs;
+#[cfg(feature = "alloc")]
+pub mod devices;
+#[cfg(feature = "alloc")]
+pub mod diffusion;
+#[cfg(feature = "alloc")]
+pub mod direct;
+#[cfg(feature = "alloc")]
+pub mod dot;
+#[cfg(all(test, feature = "std"))]
+mod fixtures;
+#[cfg(any(feature = "std", feature = "libm"))]
+mod float;
+#[cfg(feature = "alloc")]
+pub mod formats;
+#[cfg(feature = "alloc")]
+pub mod noise;
+#[cfg(feature = "alloc")]
+pub mod orientation;
+#[cfg(feature = "alloc")]
 pub mod palette;
+#[cfg(feature = "alloc")]
+mod parallel;
+pub mod pixel;
+#[cfg(feature = "alloc")]
+pub mod riemersma;
+#[cfg(feature = "alloc")]
+mod rng;
+#[cfg(feature = "alloc")]
+mod simd;
+#[cfg(any(feature = "std", feature = "libm"))]
 pub mod space;
+#[cfg(feature = "alloc")]
+pub mod stream;
 
+#[cfg(feature = "alloc")]
 pub use palette::Palette;
+#[cfg(any(feature = "std", feature = "libm"))]
 pub use space::ColourSpace;
 
-use image::RgbImage;
-use itertools::Itertools;
+#[cfg(feature = "std")]
+pub use image::Rgb;
+#[cfg(feature = "std")]
+pub use pixel::DitherPixel;
+#[cfg(not(feature = "std"))]
+pub use pixel::Rgb;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "std")]
+use image::{GenericImage, GenericImageView, ImageBuffer};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 #[cfg(feature = "theme")]
 pub mod themes {
@@ -16,65 +77,112 @@ pub mod themes {
 
 #[allow(clippy::cast_sign_loss)]
 #[allow(clippy::cast_possible_truncation)]
+#[cfg(feature = "alloc")]
 fn f32_to_u8(input: f32) -> u8 {
-    input.round().clamp(0.0, 255.0) as u8
+    float::round(input).clamp(0.0, 255.0) as u8
+}
+
+/// Quantises pixels to the nearest colours in the given colour space and given palette.
+#[cfg(feature = "alloc")]
+pub fn quantise_pixels(pixels: &mut [[u8; 3]], palette: &Palette, space: ColourSpace) {
+    for pixel in pixels {
+        *pixel = palette.get_output_colours()[palette.closest_idx(space, &Rgb(*pixel))].0;
+    }
+}
+
+/// Converts quantised pixels into the palette index of each.
+#[must_use]
+#[cfg(feature = "alloc")]
+pub fn pixels_to_indices(pixels: &[[u8; 3]], palette: &Palette) -> Vec<u8> {
+    pixels.iter().map(|&p| palette.to_idx(&Rgb(p))).collect()
+}
+
+/// Packs palette indices two to a byte, the first in the high nibble, as `image_to_bytes` does.
+/// An odd final index is paired with 0.
+#[must_use]
+#[cfg(feature = "alloc")]
+pub fn indices_to_bytes(indices: &[u8]) -> Vec<u8> {
+    indices
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+        .collect()
 }
 
 /// Quantises an image to the nearest colours in the given colour space and given palette.
-pub fn quantise_image(buf: &mut RgbImage, palette: &Palette, space: ColourSpace) {
-    buf.pixels_mut().for_each(|pixel| {
-        *pixel = palette.closest_colour(space, pixel);
-    });
+///
+/// Works on any [`DitherPixel`] at its full precision, in an image buffer backed by a `Vec` or
+/// a borrowed slice such as a framebuffer. With the `rayon` feature, pixels are quantised in
+/// parallel.
+#[cfg(feature = "std")]
+pub fn quantise_image<P, C>(buf: &mut ImageBuffer<P, C>, palette: &Palette, space: ColourSpace)
+where
+    P: DitherPixel,
+    C: Deref<Target = [P::Subpixel]> + DerefMut,
+{
+    let quantise = |pixel: &mut P| {
+        let idx = palette.closest_idx_f32(space, pixel.to_srgb());
+        *pixel = P::from_palette(palette.get_output_colours()[idx]);
+    };
+    #[cfg(feature = "rayon")]
+    buf.par_pixels_mut().for_each(quantise);
+    #[cfg(not(feature = "rayon"))]
+    buf.pixels_mut().for_each(quantise);
 }
 
 /// Quantises an image using the given palette and colour space and applies Floyd–Steinberg dithering.
-pub fn quantise_and_dither_image(buf: &mut RgbImage, palette: &Palette, space: ColourSpace) {
-    let (max_width, max_height) = buf.dimensions();
-
-    for x in 0..max_width {
-        for y in 0..max_height {
-            fn helper(input: f32, quant_err: f32, factor: f32) -> u8 {
-                f32_to_u8(input + quant_err * factor)
-            }
-
-            let old_pixel = buf[(x, y)];
-            buf[(x, y)] = palette.closest_colour(space, &old_pixel);
-
-            if x > 0 && x < max_width - 1 && y < max_height - 1 {
-                let mut right_pixel = buf[(x + 1, y)];
-                let mut bottom_left_pixel = buf[(x - 1, y + 1)];
-                let mut bottom_pixel = buf[(x, y + 1)];
-                let mut bottom_right_pixel = buf[(x + 1, y + 1)];
-
-                for ((i, &old), &new) in old_pixel.0.iter().enumerate().zip(old_pixel.0.iter()) {
-                    let quant_err = f32::from(old) - f32::from(new);
-                    right_pixel.0[i] = helper(f32::from(right_pixel.0[i]), quant_err, 7.0 / 16.0);
-                    bottom_left_pixel.0[i] =
-                        helper(f32::from(bottom_left_pixel.0[i]), quant_err, 3.0 / 16.0);
-                    bottom_pixel.0[i] = helper(f32::from(bottom_pixel.0[i]), quant_err, 5.0 / 16.0);
-                    bottom_right_pixel.0[i] =
-                        helper(f32::from(bottom_right_pixel.0[i]), quant_err, 1.0 / 16.0);
-                }
-                buf[(x + 1, y)] = right_pixel;
-                buf[(x - 1, y + 1)] = bottom_left_pixel;
-                buf[(x, y + 1)] = bottom_pixel;
-                buf[(x + 1, y + 1)] = bottom_right_pixel;
-            }
-        }
-    }
+///
+/// Error at the edges of the image is renormalised onto the neighbours inside it. See
+/// [`diffusion::diffuse`] for other options.
+#[cfg(feature = "std")]
+pub fn quantise_and_dither_image<I>(buf: &mut I, palette: &Palette, space: ColourSpace)
+where
+    I: GenericImage,
+    I::Pixel: DitherPixel,
+{
+    diffusion::diffuse(buf, palette, space, &diffusion::DiffusionOptions::default());
+}
+
+/// Converts a quantised image into the palette index of each pixel, in row-major order.
+#[must_use]
+#[cfg(feature = "std")]
+pub fn image_to_indices<I>(buf: &I, palette: &Palette) -> Vec<u8>
+where
+    I: GenericImageView,
+    I::Pixel: DitherPixel,
+{
+    pixel::row_major(buf).map(|p| p.to_idx(palette)).collect()
 }
 
 /// Encodes an image into a compact byte representation using the given palette.
-#[allow(clippy::pedantic)]
 #[must_use]
-pub fn image_to_bytes(buf: &RgbImage, palette: &Palette) -> Vec<u8> {
-    let (w, h) = buf.dimensions();
-    let mut res = Vec::with_capacity((w * h) as usize);
-    for mut i in &buf.pixels().chunks(2) {
-        let l = i.next().unwrap();
-        let r = i.next().unwrap();
-        res.push(palette.to_idx(l) << 4 | palette.to_idx(r));
-    }
+#[cfg(feature = "std")]
+pub fn image_to_bytes<I>(buf: &I, palette: &Palette) -> Vec<u8>
+where
+    I: GenericImageView,
+    I::Pixel: DitherPixel,
+{
+    indices_to_bytes(&image_to_indices(buf, palette))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::fixtures::{four_colours, gradient};
+
+    #[test]
+    fn pixels_match_image() {
+        let source = gradient(8, 5);
+        let palette = four_colours();
 
-    res
+        let mut image = source.clone();
+        quantise_image(&mut image, &palette, ColourSpace::CIELAB);
+        let mut pixels = source.clone().into_raw();
+        quantise_pixels(pixels.as_chunks_mut().0, &palette, ColourSpace::CIELAB);
+        assert_eq!(pixels, image.as_raw().as_slice());
+
+        let indices = pixels_to_indices(pixels.as_chunks().0, &palette);
+        assert_eq!(indices, image_to_indices(&image, &palette));
+        assert_eq!(indices_to_bytes(&indices), image_to_bytes(&image, &palette));
+        assert_eq!(indices_to_bytes(&[1, 2, 3]), [0x12, 0x30]);
+    }
 }
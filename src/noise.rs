@@ -0,0 +1,189 @@
+//! Dithering with seeded random noise.
+//!
+//! Noise depends only on the seed and each pixel's position, so the same seed always gives the
+//! same output, and with the `rayon` feature pixels are processed in parallel without changing
+//! the result.
+
+use crate::parallel::for_each_position;
+#[cfg(feature = "std")]
+use crate::parallel::parallel_map;
+#[cfg(feature = "std")]
+use crate::pixel::{row_major, write_indices};
+use crate::rng::position_noise;
+use crate::{ColourSpace, Palette, f32_to_u8, quantise_pixels};
+#[cfg(feature = "std")]
+use crate::{DitherPixel, quantise_image};
+#[cfg(feature = "std")]
+use image::{GenericImage, RgbImage};
+
+/// Quantises an image using the given palette and colour space, comparing each pixel against a
+/// random threshold.
+///
+/// Palette colours are treated as evenly spaced levels, so the noise spans the gap between two
+/// adjacent levels. With a two colour palette this is plain white-noise threshold dithering.
+///
+/// Pixels are read at their full precision, see [`DitherPixel`].
+#[allow(clippy::cast_possible_truncation)]
+#[cfg(feature = "std")]
+pub fn white_noise_dither<I>(buf: &mut I, palette: &Palette, space: ColourSpace, seed: u64)
+where
+    I: GenericImage,
+    I::Pixel: DitherPixel,
+{
+    let width = buf.width() as usize;
+    let source: Vec<[f32; 3]> = row_major(buf).map(|p| p.to_srgb()).collect();
+    let dither = white_noise(palette, space, seed);
+    let indices = parallel_map(source.len(), |i| {
+        dither((i % width) as u32, (i / width) as u32, source[i])
+    });
+    write_indices(buf, indices, palette);
+}
+
+/// Like [`white_noise_dither`], for an image `width` pixels wide stored as row-major pixels.
+pub fn white_noise_dither_pixels(
+    pixels: &mut [[u8; 3]],
+    width: u32,
+    palette: &Palette,
+    space: ColourSpace,
+    seed: u64,
+) {
+    let dither = white_noise(palette, space, seed);
+    for_each_position(pixels, width, |x, y, pixel| {
+        *pixel = palette.get_output_colours()[dither(x, y, pixel.map(f32::from))].0;
+    });
+}
+
+/// Returns the step of [`white_noise_dither`], mapping a pixel's position and sRGB value to its
+/// palette index.
+#[allow(clippy::cast_precision_loss)]
+fn white_noise(
+    palette: &Palette,
+    space: ColourSpace,
+    seed: u64,
+) -> impl Fn(u32, u32, [f32; 3]) -> usize + Send + Sync + '_ {
+    let levels = palette.get_colours().len().max(2);
+    let step = 255.0 / (levels - 1) as f32;
+    move |x, y, pixel| {
+        let offset = (position_noise(seed, x, y) - 0.5) * step;
+        palette.closest_idx_f32(space, pixel.map(|c| c + offset))
+    }
+}
+
+/// Adds uniform noise of up to `amount / 2` either way to every pixel, the same on each channel
+/// so it reads as film grain.
+///
+/// Quantise or dither the image afterwards, e.g. with [`quantise_with_noise`]. Like the channel
+/// quantisers in [`direct`](crate::direct), this works on 8-bit channel values, so it only takes
+/// an [`RgbImage`].
+#[cfg(feature = "std")]
+pub fn inject_noise(buf: &mut RgbImage, amount: f32, seed: u64) {
+    let width = buf.width();
+    inject_noise_pixels(buf.as_chunks_mut().0, width, amount, seed);
+}
+
+/// Like [`inject_noise`], for an image `width` pixels wide stored as row-major pixels.
+pub fn inject_noise_pixels(pixels: &mut [[u8; 3]], width: u32, amount: f32, seed: u64) {
+    for_each_position(pixels, width, grain(amount, seed));
+}
+
+/// Returns the step of [`inject_noise`] for a single pixel.
+fn grain(amount: f32, seed: u64) -> impl Fn(u32, u32, &mut [u8; 3]) + Send + Sync {
+    move |x, y, pixel| {
+        let offset = (position_noise(seed, x, y) - 0.5) * amount;
+        *pixel = pixel.map(|c| f32_to_u8(f32::from(c) + offset));
+    }
+}
+
+/// Adds noise to an image with [`inject_noise`], then quantises it with [`quantise_image`].
+#[cfg(feature = "std")]
+pub fn quantise_with_noise(
+    buf: &mut RgbImage,
+    palette: &Palette,
+    space: ColourSpace,
+    amount: f32,
+    seed: u64,
+) {
+    inject_noise(buf, amount, seed);
+    quantise_image(buf, palette, space);
+}
+
+/// Like [`quantise_with_noise`], for an image `width` pixels wide stored as row-major pixels.
+pub fn quantise_with_noise_pixels(
+    pixels: &mut [[u8; 3]],
+    width: u32,
+    palette: &Palette,
+    space: ColourSpace,
+    amount: f32,
+    seed: u64,
+) {
+    inject_noise_pixels(pixels, width, amount, seed);
+    quantise_pixels(pixels, palette, space);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::fixtures::{four_colours, gradient};
+    use alloc::vec;
+
+    #[test]
+    fn reproducible() {
+        let dither = |seed| {
+            let mut pixels = vec![[64; 3]; 32 * 32];
+            white_noise_dither_pixels(
+                &mut pixels,
+                32,
+                &Palette::monochrome(),
+                ColourSpace::RGB,
+                seed,
+            );
+            pixels
+        };
+        assert_eq!(dither(7), dither(7));
+        assert_ne!(dither(7), dither(8));
+        let white = dither(7).iter().filter(|p| p[0] == 255).count();
+        assert!((200..=310).contains(&white), "{white}");
+
+        let grain = |seed| {
+            let mut pixels = vec![[100, 150, 200]; 16 * 16];
+            quantise_with_noise_pixels(
+                &mut pixels,
+                16,
+                &Palette::monochrome(),
+                ColourSpace::RGB,
+                64.0,
+                seed,
+            );
+            pixels
+        };
+        assert_eq!(grain(3), grain(3));
+        assert_ne!(grain(3), grain(4));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn matches_serial() {
+        // with the `rayon` feature, pixels are processed in parallel but give the same result
+        let source = gradient(40, 30);
+        let palette = four_colours();
+
+        let mut parallel = source.clone();
+        white_noise_dither(&mut parallel, &palette, ColourSpace::CIELAB, 11);
+        let mut serial = source.clone();
+        let dither = white_noise(&palette, ColourSpace::CIELAB, 11);
+        for (x, y, pixel) in serial.enumerate_pixels_mut() {
+            *pixel = palette.get_output_colours()[dither(x, y, pixel.0.map(f32::from))];
+        }
+        assert_eq!(parallel, serial);
+
+        let mut parallel = source.clone();
+        inject_noise(&mut parallel, 48.0, 5);
+        let mut serial = source;
+        let add = grain(48.0, 5);
+        for (x, y, pixel) in serial.enumerate_pixels_mut() {
+            add(x, y, &mut pixel.0);
+        }
+        assert_eq!(parallel, serial);
+    }
+}
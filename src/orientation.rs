@@ -0,0 +1,151 @@
+//! Orientation transforms applied when packing, so images can be dithered in their logical
+//! orientation but emitted in a panel's native scan order.
+
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use image::RgbImage;
+
+/// Clockwise rotation.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Half,
+    Clockwise270,
+}
+
+/// Transform from an image's logical orientation to the order it's emitted in. The rotation is
+/// applied first, then the flips.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    /// Mirror left to right.
+    pub flip_horizontal: bool,
+    /// Mirror top to bottom.
+    pub flip_vertical: bool,
+}
+
+impl Orientation {
+    /// Orientation that leaves images unchanged.
+    pub const IDENTITY: Self = Self::rotated(Rotation::None);
+
+    /// Creates an orientation that only rotates.
+    #[must_use]
+    pub const fn rotated(rotation: Rotation) -> Self {
+        Self {
+            rotation,
+            flip_horizontal: false,
+            flip_vertical: false,
+        }
+    }
+
+    /// Returns the size of a `width` by `height` image once transformed.
+    #[must_use]
+    pub fn output_size(self, width: u32, height: u32) -> (u32, u32) {
+        match self.rotation {
+            Rotation::None | Rotation::Half => (width, height),
+            Rotation::Clockwise90 | Rotation::Clockwise270 => (height, width),
+        }
+    }
+
+    /// Returns the size an image needs to be to have the given size once transformed.
+    #[must_use]
+    pub fn input_size(self, width: u32, height: u32) -> (u32, u32) {
+        // rotations by 90 degrees and flips are their own inverse as far as size goes
+        self.output_size(width, height)
+    }
+
+    /// Returns the position in a `width` by `height` image of the pixel that ends up at `(x, y)`
+    /// once transformed.
+    #[must_use]
+    pub fn source_position(self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        let (out_w, out_h) = self.output_size(width, height);
+        let x = if self.flip_horizontal {
+            out_w - 1 - x
+        } else {
+            x
+        };
+        let y = if self.flip_vertical { out_h - 1 - y } else { y };
+        match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Half => (width - 1 - x, height - 1 - y),
+            Rotation::Clockwise90 => (y, height - 1 - x),
+            Rotation::Clockwise270 => (width - 1 - y, x),
+        }
+    }
+
+    /// Transforms row-major data for a `width` by `height` image, returning it along with the
+    /// new width and height.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` has fewer than `width * height` values.
+    #[must_use]
+    pub fn apply<T: Copy>(self, data: &[T], width: u32, height: u32) -> (Vec<T>, u32, u32) {
+        let (out_w, out_h) = self.output_size(width, height);
+        let res = (0..out_h)
+            .flat_map(|y| (0..out_w).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (sx, sy) = self.source_position(x, y, width, height);
+                data[sy as usize * width as usize + sx as usize]
+            })
+            .collect();
+        (res, out_w, out_h)
+    }
+
+    /// Transforms an image, e.g. a dithered image before it's passed to an encoder.
+    #[must_use]
+    #[cfg(feature = "std")]
+    pub fn apply_image(self, buf: &RgbImage) -> RgbImage {
+        let (width, height) = buf.dimensions();
+        let (out_w, out_h) = self.output_size(width, height);
+        RgbImage::from_fn(out_w, out_h, |x, y| {
+            let (sx, sy) = self.source_position(x, y, width, height);
+            *buf.get_pixel(sx, sy)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn transforms() {
+        // 3x2:
+        // 0 1 2
+        // 3 4 5
+        let data = [0, 1, 2, 3, 4, 5];
+        let cases = [
+            (
+                Orientation::rotated(Rotation::Clockwise90),
+                vec![3, 0, 4, 1, 5, 2],
+            ),
+            (Orientation::rotated(Rotation::Half), vec![5, 4, 3, 2, 1, 0]),
+            (
+                Orientation::rotated(Rotation::Clockwise270),
+                vec![2, 5, 1, 4, 0, 3],
+            ),
+            (
+                Orientation {
+                    flip_horizontal: true,
+                    ..Orientation::IDENTITY
+                },
+                vec![2, 1, 0, 5, 4, 3],
+            ),
+            (
+                Orientation {
+                    rotation: Rotation::Clockwise90,
+                    flip_vertical: true,
+                    ..Orientation::IDENTITY
+                },
+                vec![5, 2, 4, 1, 3, 0],
+            ),
+        ];
+        for (orientation, exp) in cases {
+            assert_eq!(orientation.apply(&data, 3, 2).0, exp, "{orientation:?}");
+        }
+    }
+}
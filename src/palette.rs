@@ -1,17 +1,33 @@
-use crate::f32_to_u8;
-use crate::space::{ColourSpace, EuclideanDistance};
+use crate::simd::Channels;
+use crate::space::ColourSpace;
+use crate::{Rgb, f32_to_u8};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use anyhow::Context;
+use anyhow::Result;
 use anyhow::anyhow;
-use anyhow::{Context, Result};
-use image::Rgb;
+#[cfg(feature = "std")]
 use std::path::Path;
 
 /// Palette
 /// Represents a colour palette as a collection of RGB colours.
-pub struct Palette(Vec<Rgb<u8>>);
+///
+/// A palette can be paired (see [`Palette::paired`]), in which case pixels are matched against
+/// one set of colours but written out as the colour at the same index in another. One index can
+/// also be reserved for transparent pixels (see [`Palette::with_transparent`]).
+pub struct Palette {
+    colours: Vec<Rgb<u8>>,
+    output: Vec<Rgb<u8>>,
+    transparent: Option<u8>,
+    /// `colours` in each colour space, for fast searching.
+    rgb: Channels,
+    cielab: Channels,
+}
 
 impl From<&[[u8; 3]]> for Palette {
     fn from(value: &[[u8; 3]]) -> Self {
-        Self(value.iter().map(|&i| image::Rgb(i)).collect())
+        let colours: Vec<_> = value.iter().map(|&i| Rgb(i)).collect();
+        Self::new(colours.clone(), colours)
     }
 }
 impl From<Vec<[u8; 3]>> for Palette {
@@ -21,6 +37,69 @@ impl From<Vec<[u8; 3]>> for Palette {
 }
 
 impl Palette {
+    fn new(colours: Vec<Rgb<u8>>, output: Vec<Rgb<u8>>) -> Self {
+        Self::with_channels(colours, output, None)
+    }
+
+    fn with_channels(colours: Vec<Rgb<u8>>, output: Vec<Rgb<u8>>, transparent: Option<u8>) -> Self {
+        let channels = |space: ColourSpace| {
+            let coordinates: Vec<[f32; 3]> = colours
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    // infinitely far away, so it's never the closest colour
+                    if transparent.is_some_and(|t| usize::from(t) == i) {
+                        [f32::INFINITY; 3]
+                    } else {
+                        space.coordinates(c.0.map(f32::from))
+                    }
+                })
+                .collect();
+            Channels::new(&coordinates)
+        };
+        Self {
+            rgb: channels(ColourSpace::RGB),
+            cielab: channels(ColourSpace::CIELAB),
+            colours,
+            output,
+            transparent,
+        }
+    }
+
+    /// Reserves the colour at `idx` for transparent pixels, so it's never matched against opaque
+    /// ones, as in GIF and other indexed formats.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `idx` is outside the palette, or it's the only colour.
+    pub fn with_transparent(self, idx: u8) -> Result<Self> {
+        if usize::from(idx) >= self.colours.len() {
+            return Err(anyhow!(
+                "Transparent index {} is outside a palette of {} colours",
+                idx,
+                self.colours.len()
+            ));
+        }
+        if self.colours.len() == 1 {
+            return Err(anyhow!(
+                "Palette needs an opaque colour besides the transparent one"
+            ));
+        }
+        Ok(Self::with_channels(self.colours, self.output, Some(idx)))
+    }
+
+    /// Returns the index reserved for transparent pixels, if there is one.
+    #[must_use]
+    pub fn transparent_idx(&self) -> Option<u8> {
+        self.transparent
+    }
+
+    /// Creates a two colour palette of black and white, for 1-bit outputs.
+    #[must_use]
+    pub fn monochrome() -> Self {
+        Self::from([[0, 0, 0], [255, 255, 255]].as_slice())
+    }
+
     /// Creates a new Palette by blending 2 palettes together based on the given saturation.
     ///
     /// # Errors
@@ -52,6 +131,30 @@ impl Palette {
         Ok(res.into())
     }
 
+    /// Creates a paired palette, where pixels are matched and quantisation error is measured
+    /// against the `perceived` colours, but the `drive` colour at the same index is output.
+    ///
+    /// This lets dithering account for what a display actually shows while still emitting the
+    /// colours its controller expects.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the palettes have different lengths.
+    pub fn paired(perceived: &[[u8; 3]], drive: &[[u8; 3]]) -> Result<Self> {
+        if perceived.len() != drive.len() {
+            return Err(anyhow!(
+                "Paired palettes should be the same length, got: {} and {}",
+                perceived.len(),
+                drive.len()
+            ));
+        }
+
+        Ok(Self::new(
+            perceived.iter().map(|&i| Rgb(i)).collect(),
+            drive.iter().map(|&i| Rgb(i)).collect(),
+        ))
+    }
+
     /// Creates a new Palette by parsing a string of hexadecimal colour values.
     ///
     /// The text is split by lines, with one colour per line.
@@ -91,9 +194,10 @@ impl Palette {
     /// # Errors
     ///
     /// This function will return an error if is unable to parse the yaml file.
+    #[cfg(feature = "std")]
     pub fn from_tinted_scheme_yaml<P: AsRef<Path> + ToString>(path: &P) -> Result<Self> {
         let fc = std::fs::read_to_string(path)
-            .context(format!("Unable to find file '{}'", &path.to_string()))?;
+            .context(format!("Unable to find file '{}'", path.to_string()))?;
         let lines = fc.lines();
         let mut res = Vec::new();
         for p_line in lines {
@@ -118,39 +222,104 @@ impl Palette {
         Ok(res.into())
     }
 
-    /// Returns the palette colours.
+    /// Returns the palette colours that pixels are matched against.
     #[must_use]
     pub fn get_colours(&self) -> &[Rgb<u8>] {
-        &self.0
+        &self.colours
     }
 
-    /// Finds the closest colour in the palette to a given pixel using the specified colour space.
+    /// Returns the palette colours written to quantised images. These are the same as
+    /// [`Palette::get_colours`] unless the palette is paired.
     #[must_use]
-    pub fn closest_colour(&self, space: ColourSpace, pixel: &Rgb<u8>) -> Rgb<u8> {
-        let mut closest_colour_idx = 0;
-        let mut closest_dist: f32 = f32::MAX;
-
-        for (i, palette_colour) in self.0.iter().enumerate() {
-            let dist: f32 = space.distance_sq(*palette_colour, *pixel);
-            if dist < closest_dist {
-                closest_colour_idx = i;
-                closest_dist = dist;
-            }
-        }
+    pub fn get_output_colours(&self) -> &[Rgb<u8>] {
+        &self.output
+    }
 
-        self.0[closest_colour_idx]
+    /// Finds the index of the closest colour in the palette to a given pixel using the specified
+    /// colour space. This is never the transparent index.
+    #[must_use]
+    pub fn closest_idx(&self, space: ColourSpace, pixel: &Rgb<u8>) -> usize {
+        self.closest_idx_f32(space, pixel.0.map(f32::from))
     }
 
-    /// Returns the index of the palette colour of the pixel provided.
+    /// Like [`Palette::closest_idx`], for a pixel whose channels may lie outside [0, 255], such as
+    /// one with accumulated dithering error.
     ///
-    /// If the pixel isn't a palette colour, returns 0.
+    /// Palette colours are converted into each colour space when the palette is created, and
+    /// compared against the pixel several at a time with SIMD instructions where available.
+    pub(crate) fn closest_idx_f32(&self, space: ColourSpace, pixel: [f32; 3]) -> usize {
+        let channels = match space {
+            ColourSpace::RGB => &self.rgb,
+            ColourSpace::CIELAB => &self.cielab,
+        };
+        channels.nearest(space.coordinates(pixel))
+    }
+
+    /// Finds the closest colour in the palette to a given pixel using the specified colour space,
+    /// returning the colour quantisation writes, i.e. the output colour of a paired palette.
+    #[must_use]
+    pub fn closest_colour(&self, space: ColourSpace, pixel: &Rgb<u8>) -> Rgb<u8> {
+        self.output[self.closest_idx(space, pixel)]
+    }
+
+    /// Returns the index of the palette colour of the pixel provided, as written to quantised
+    /// images.
+    ///
+    /// If the pixel isn't a palette colour, returns 0. The transparent index is skipped, since
+    /// it's never the colour of an opaque pixel.
     #[must_use]
     pub fn to_idx(&self, pixel: &Rgb<u8>) -> u8 {
-        for (i, c) in self.0.iter().enumerate() {
-            if *c == *pixel {
+        for (i, c) in self.output.iter().enumerate() {
+            if *c == *pixel && self.transparent != Some(i as u8) {
                 return i as u8;
             }
         }
         0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paired_matches_perceived_and_outputs_drive() {
+        let palette = Palette::paired(
+            &[[40, 40, 40], [180, 180, 180]],
+            &[[0, 0, 0], [255, 255, 255]],
+        )
+        .unwrap();
+        // closer to the perceived white than the nominal white
+        let idx = palette.closest_idx(ColourSpace::RGB, &Rgb([170, 170, 170]));
+        assert_eq!(idx, 1);
+        assert_eq!(palette.to_idx(&palette.get_output_colours()[idx]), 1);
+        assert_eq!(
+            palette.closest_colour(ColourSpace::RGB, &Rgb([170, 170, 170])),
+            Rgb([255, 255, 255])
+        );
+        assert!(Palette::paired(&[[0, 0, 0]], &[]).is_err());
+    }
+
+    #[test]
+    fn transparent_is_never_matched() {
+        let palette = Palette::from([[0, 0, 0], [0, 0, 0], [255, 255, 255]].as_slice())
+            .with_transparent(0)
+            .unwrap();
+        assert_eq!(palette.transparent_idx(), Some(0));
+        for space in [ColourSpace::RGB, ColourSpace::CIELAB] {
+            assert_eq!(palette.closest_idx(space, &Rgb([10, 10, 10])), 1);
+        }
+        assert_eq!(palette.to_idx(&Rgb([0, 0, 0])), 1);
+        for space in [ColourSpace::RGB, ColourSpace::CIELAB] {
+            for pixel in [[f32::NAN; 3], [f32::MAX; 3]] {
+                assert_eq!(palette.closest_idx_f32(space, pixel), 1);
+            }
+        }
+        assert!(Palette::monochrome().with_transparent(2).is_err());
+        assert!(
+            Palette::from([[0, 0, 0]].as_slice())
+                .with_transparent(0)
+                .is_err()
+        );
+    }
+}
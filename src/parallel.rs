@@ -0,0 +1,84 @@
+//! Splitting independent work across threads.
+//!
+//! With the `rayon` feature this uses rayon's thread pool, otherwise it spawns scoped threads.
+//! Without `std` there are no threads, so work runs in order on the current one.
+
+use alloc::vec::Vec;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+#[cfg(all(feature = "std", not(feature = "rayon")))]
+use std::num::NonZeroUsize;
+#[cfg(all(feature = "std", not(feature = "rayon")))]
+use std::thread;
+
+/// Maps `f` over `0..len` using every available core, returning the results in order.
+#[cfg(feature = "rayon")]
+pub(crate) fn parallel_map<T, F>(len: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    (0..len).into_par_iter().map(&f).collect()
+}
+
+/// Maps `f` over `0..len` using every available core, returning the results in order.
+#[cfg(all(feature = "std", not(feature = "rayon")))]
+pub(crate) fn parallel_map<T, F>(len: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    if threads == 1 || len < 2 {
+        return (0..len).map(f).collect();
+    }
+
+    let chunk = len.div_ceil(threads);
+    let f = &f;
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..len)
+            .step_by(chunk)
+            .map(|start| {
+                s.spawn(move || (start..len.min(start + chunk)).map(f).collect::<Vec<_>>())
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Maps `f` over `0..len`, returning the results in order.
+#[cfg(not(feature = "std"))]
+pub(crate) fn parallel_map<T, F>(len: usize, f: F) -> Vec<T>
+where
+    F: Fn(usize) -> T,
+{
+    (0..len).map(f).collect()
+}
+
+/// Calls `f` with the position of every pixel of a row-major image `width` pixels wide, in
+/// parallel with the `rayon` feature.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn for_each_position<P, F>(pixels: &mut [P], width: u32, f: F)
+where
+    P: Send,
+    F: Fn(u32, u32, &mut P) + Send + Sync,
+{
+    if width == 0 {
+        return;
+    }
+    let row = |(y, row): (usize, &mut [P])| {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            f(x as u32, y as u32, pixel);
+        }
+    };
+    #[cfg(feature = "rayon")]
+    pixels
+        .par_chunks_mut(width as usize)
+        .enumerate()
+        .for_each(row);
+    #[cfg(not(feature = "rayon"))]
+    pixels.chunks_mut(width as usize).enumerate().for_each(row);
+}
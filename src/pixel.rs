@@ -0,0 +1,309 @@
+//! Pixel types that can be quantised.
+//!
+//! Pixels are read at their full precision, so 16-bit and float images are dithered without
+//! first being converted to 8 bits. Quantised pixels are palette colours, which every supported
+//! type represents exactly.
+//!
+//! Everything that matches pixels against a [`crate::Palette`] takes any of these types. Functions that
+//! work on 8-bit channel values instead, i.e. [`crate::direct`], [`crate::noise::inject_noise`],
+//! [`crate::formats::rgb`] and the PGM, PPM and PAM writers, only take an `RgbImage`.
+
+#[cfg(feature = "std")]
+use crate::space::luma;
+#[cfg(feature = "std")]
+use crate::{Palette, f32_to_u8};
+#[cfg(feature = "std")]
+use image::{GenericImage, GenericImageView, Luma, Pixel, Rgb};
+
+/// A stand-in for `image::Rgb` when the `image` crate isn't available.
+#[cfg(not(feature = "std"))]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct Rgb<T>(pub [T; 3]);
+
+/// A pixel that can be quantised to a palette.
+///
+/// Implemented for `Rgb` and `Luma` with `u8`, `u16` and `f32` channels. Float channels are
+/// nominally in [0, 1], and may go outside it, e.g. in HDR images. Greyscale pixels are matched
+/// as a grey and written as the luma of the palette colour, so palette colours with the same
+/// luma can't be told apart once written.
+#[cfg(feature = "std")]
+pub trait DitherPixel: Pixel<Subpixel: Send + Sync> + Send + Sync {
+    /// Returns the colour as sRGB with channels nominally in [0, 255], without rounding.
+    fn to_srgb(&self) -> [f32; 3];
+
+    /// Converts a palette colour to this pixel type.
+    fn from_palette(colour: Rgb<u8>) -> Self;
+
+    /// Returns the palette index of a quantised pixel, see [`Palette::to_idx`].
+    fn to_idx(&self, palette: &Palette) -> u8 {
+        palette.to_idx(&Rgb(self.to_srgb().map(f32_to_u8)))
+    }
+}
+
+/// Returns the index of the first palette colour written as the given grey, skipping the
+/// transparent index, or 0 if there isn't one.
+#[cfg(feature = "std")]
+fn grey_to_idx(palette: &Palette, grey: f32) -> u8 {
+    let grey = f32_to_u8(grey);
+    (0..=u8::MAX)
+        .zip(palette.get_output_colours())
+        .find(|&(i, &c)| luma(c) == grey && palette.transparent_idx() != Some(i))
+        .map_or(0, |(i, _)| i)
+}
+
+/// Returns the pixels of an image in row-major order.
+///
+/// Unlike `GenericImageView::pixels`, this doesn't panic on an image with no columns.
+#[cfg(feature = "std")]
+pub(crate) fn row_major<I: GenericImageView>(buf: &I) -> impl Iterator<Item = I::Pixel> + '_ {
+    let (width, height) = buf.dimensions();
+    (0..height).flat_map(move |y| (0..width).map(move |x| buf.get_pixel(x, y)))
+}
+
+/// Writes the palette colour of each index to an image, in row-major order.
+#[cfg(feature = "std")]
+pub(crate) fn write_indices<I>(buf: &mut I, indices: Vec<usize>, palette: &Palette)
+where
+    I: GenericImage,
+    I::Pixel: DitherPixel,
+{
+    let (width, height) = buf.dimensions();
+    let positions = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)));
+    for ((x, y), idx) in positions.zip(indices) {
+        buf.put_pixel(
+            x,
+            y,
+            I::Pixel::from_palette(palette.get_output_colours()[idx]),
+        );
+    }
+}
+
+#[cfg(feature = "std")]
+impl DitherPixel for Rgb<u8> {
+    fn to_srgb(&self) -> [f32; 3] {
+        self.0.map(f32::from)
+    }
+
+    fn from_palette(colour: Rgb<u8>) -> Self {
+        colour
+    }
+}
+
+#[cfg(feature = "std")]
+impl DitherPixel for Rgb<u16> {
+    fn to_srgb(&self) -> [f32; 3] {
+        self.0.map(|c| f32::from(c) / 257.0)
+    }
+
+    fn from_palette(colour: Rgb<u8>) -> Self {
+        Rgb(colour.0.map(|c| u16::from(c) * 257))
+    }
+}
+
+#[cfg(feature = "std")]
+impl DitherPixel for Rgb<f32> {
+    fn to_srgb(&self) -> [f32; 3] {
+        self.0.map(|c| c * 255.0)
+    }
+
+    fn from_palette(colour: Rgb<u8>) -> Self {
+        Rgb(colour.0.map(|c| f32::from(c) / 255.0))
+    }
+}
+
+#[cfg(feature = "std")]
+impl DitherPixel for Luma<u8> {
+    fn to_srgb(&self) -> [f32; 3] {
+        [f32::from(self.0[0]); 3]
+    }
+
+    fn from_palette(colour: Rgb<u8>) -> Self {
+        Luma([luma(colour)])
+    }
+
+    fn to_idx(&self, palette: &Palette) -> u8 {
+        grey_to_idx(palette, self.to_srgb()[0])
+    }
+}
+
+#[cfg(feature = "std")]
+impl DitherPixel for Luma<u16> {
+    fn to_srgb(&self) -> [f32; 3] {
+        [f32::from(self.0[0]) / 257.0; 3]
+    }
+
+    fn from_palette(colour: Rgb<u8>) -> Self {
+        Luma([u16::from(luma(colour)) * 257])
+    }
+
+    fn to_idx(&self, palette: &Palette) -> u8 {
+        grey_to_idx(palette, self.to_srgb()[0])
+    }
+}
+
+#[cfg(feature = "std")]
+impl DitherPixel for Luma<f32> {
+    fn to_srgb(&self) -> [f32; 3] {
+        [self.0[0] * 255.0; 3]
+    }
+
+    fn from_palette(colour: Rgb<u8>) -> Self {
+        Luma([f32::from(luma(colour)) / 255.0])
+    }
+
+    fn to_idx(&self, palette: &Palette) -> u8 {
+        grey_to_idx(palette, self.to_srgb()[0])
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::dbs::{DbsOptions, direct_binary_search};
+    use crate::diffusion::{DiffusionOptions, ErrorSpace, diffuse, diffuse_wavefront};
+    use crate::dot::dot_diffuse;
+    use crate::fixtures::{four_colours, gradient};
+    use crate::noise::white_noise_dither;
+    use crate::riemersma::{RiemersmaOptions, riemersma};
+    use crate::{ColourSpace, Palette, image_to_indices, quantise_image};
+    use image::{ImageBuffer, RgbImage};
+
+    #[test]
+    fn empty_images() {
+        let palette = Palette::monochrome();
+        for (w, h) in [(0, 3), (3, 0)] {
+            let mut buf = RgbImage::new(w, h);
+            diffuse(
+                &mut buf,
+                &palette,
+                ColourSpace::RGB,
+                &DiffusionOptions::default(),
+            );
+            assert!(image_to_indices(&buf, &palette).is_empty());
+        }
+    }
+
+    #[test]
+    fn precisions_agree() {
+        let source = gradient(20, 12);
+        let palette = four_colours();
+        let options = DiffusionOptions::default();
+        let mut expected = source.clone();
+        diffuse(&mut expected, &palette, ColourSpace::CIELAB, &options);
+        let expected = image_to_indices(&expected, &palette);
+
+        let mut wide = ImageBuffer::from_fn(20, 12, |x, y| {
+            Rgb::<u16>::from_palette(*source.get_pixel(x, y))
+        });
+        diffuse(&mut wide, &palette, ColourSpace::CIELAB, &options);
+        assert_eq!(image_to_indices(&wide, &palette), expected);
+
+        let mut float = ImageBuffer::from_fn(20, 12, |x, y| {
+            Rgb::<f32>::from_palette(*source.get_pixel(x, y))
+        });
+        diffuse(&mut float, &palette, ColourSpace::CIELAB, &options);
+        assert_eq!(image_to_indices(&float, &palette), expected);
+
+        // a borrowed framebuffer
+        let mut raw = source.clone().into_raw();
+        let mut framebuffer =
+            ImageBuffer::<Rgb<u8>, _>::from_raw(20, 12, raw.as_mut_slice()).unwrap();
+        diffuse(&mut framebuffer, &palette, ColourSpace::CIELAB, &options);
+        assert_eq!(image_to_indices(&framebuffer, &palette), expected);
+    }
+
+    #[test]
+    fn other_algorithms_take_any_precision() {
+        type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+        let source = gradient(20, 12);
+        let palette = four_colours();
+        let space = ColourSpace::CIELAB;
+        let wide = ImageBuffer::from_fn(20, 12, |x, y| {
+            Rgb::<u16>::from_palette(*source.get_pixel(x, y))
+        });
+        let check = |dither: &dyn Fn(&mut RgbImage), dither_wide: &dyn Fn(&mut Rgb16Image)| {
+            let mut expected = source.clone();
+            dither(&mut expected);
+            let mut actual = wide.clone();
+            dither_wide(&mut actual);
+            assert_eq!(
+                image_to_indices(&actual, &palette),
+                image_to_indices(&expected, &palette)
+            );
+        };
+
+        let options = RiemersmaOptions::default();
+        check(&|buf| riemersma(buf, &palette, space, &options), &|buf| {
+            riemersma(buf, &palette, space, &options)
+        });
+        let error_space = ErrorSpace::default();
+        check(
+            &|buf| dot_diffuse(buf, &palette, space, error_space),
+            &|buf| dot_diffuse(buf, &palette, space, error_space),
+        );
+        check(&|buf| white_noise_dither(buf, &palette, space, 3), &|buf| {
+            white_noise_dither(buf, &palette, space, 3)
+        });
+        let options = DiffusionOptions::default();
+        check(
+            &|buf| diffuse_wavefront(buf, &palette, space, &options, 8),
+            &|buf| diffuse_wavefront(buf, &palette, space, &options, 8),
+        );
+        check(
+            &|buf| {
+                let start = buf.clone();
+                quantise_image(buf, &palette, space);
+                direct_binary_search(buf, &start, &palette, &DbsOptions::default()).unwrap();
+            },
+            &|buf| {
+                let start = buf.clone();
+                quantise_image(buf, &palette, space);
+                direct_binary_search(buf, &start, &palette, &DbsOptions::default()).unwrap();
+            },
+        );
+    }
+
+    #[test]
+    fn keeps_precision() {
+        // both round to 128 in 8 bits
+        let dither = |value| {
+            let mut buf = ImageBuffer::from_pixel(32, 32, Luma::<u16>([value]));
+            diffuse(
+                &mut buf,
+                &Palette::monochrome(),
+                ColourSpace::RGB,
+                &DiffusionOptions::default(),
+            );
+            buf
+        };
+        let (low, high) = (dither(128 * 257 - 100), dither(128 * 257 + 100));
+        assert!(low.pixels().all(|p| p.0[0] == 0 || p.0[0] == u16::MAX));
+        let white = |buf: &ImageBuffer<Luma<u16>, Vec<u16>>| {
+            buf.pixels().filter(|p| p.0[0] == u16::MAX).count()
+        };
+        assert!(white(&low) < white(&high));
+
+        let mut grey = ImageBuffer::from_pixel(4, 4, Luma::<f32>([0.9]));
+        quantise_image(&mut grey, &Palette::monochrome(), ColourSpace::RGB);
+        assert!(grey.pixels().all(|p| p.0[0] == 1.0));
+    }
+
+    #[test]
+    fn greyscale_indices_with_colour_palette() {
+        let palette = Palette::from([[255, 0, 0], [0, 0, 200]].as_slice());
+        assert_eq!(palette.closest_idx(ColourSpace::RGB, &Rgb([20; 3])), 1);
+
+        let mut narrow = ImageBuffer::from_pixel(2, 1, Luma::<u8>([20]));
+        quantise_image(&mut narrow, &palette, ColourSpace::RGB);
+        assert_eq!(image_to_indices(&narrow, &palette), [1, 1]);
+
+        let mut wide = ImageBuffer::from_pixel(2, 1, Luma::<u16>([20 * 257]));
+        quantise_image(&mut wide, &palette, ColourSpace::RGB);
+        assert_eq!(image_to_indices(&wide, &palette), [1, 1]);
+
+        let mut float = ImageBuffer::from_pixel(2, 1, Luma::<f32>([20.0 / 255.0]));
+        quantise_image(&mut float, &palette, ColourSpace::RGB);
+        assert_eq!(image_to_indices(&float, &palette), [1, 1]);
+    }
+}
@@ -0,0 +1,233 @@
+//! Riemersma dithering, which visits pixels along a space-filling curve and diffuses error
+//! through a short history of recent errors.
+//!
+//! <https://www.compuphase.com/riemer.htm>
+//!
+//! Error only travels a short distance along the curve, so it stays local and has no preferred
+//! direction.
+
+#[cfg(feature = "std")]
+use crate::DitherPixel;
+use crate::diffusion::ErrorSpace;
+use crate::float::powf;
+#[cfg(feature = "std")]
+use crate::pixel::{row_major, write_indices};
+use crate::{ColourSpace, Palette};
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use image::GenericImage;
+
+/// Options for Riemersma dithering.
+#[derive(Copy, Clone, Debug)]
+pub struct RiemersmaOptions {
+    /// Number of recent errors diffused into each pixel.
+    pub history: usize,
+    /// Weight of the oldest error in the history relative to the newest, between 0 and 1. Weights
+    /// fall off exponentially in between.
+    pub ratio: f32,
+    pub error_space: ErrorSpace,
+}
+
+impl Default for RiemersmaOptions {
+    fn default() -> Self {
+        Self {
+            history: 16,
+            ratio: 1.0 / 16.0,
+            error_space: ErrorSpace::default(),
+        }
+    }
+}
+
+/// Quantises an image using the given palette and colour space, diffusing error along a
+/// generalised Hilbert curve.
+#[cfg(feature = "std")]
+pub fn riemersma<I>(buf: &mut I, palette: &Palette, space: ColourSpace, options: &RiemersmaOptions)
+where
+    I: GenericImage,
+    I::Pixel: DitherPixel,
+{
+    let (width, height) = buf.dimensions();
+    let source: Vec<[f32; 3]> = row_major(buf).map(|p| p.to_srgb()).collect();
+    let indices = riemersma_indices(&source, width, height, palette, space, options);
+    write_indices(buf, indices, palette);
+}
+
+/// Like [`riemersma`], for a `width` by `height` image stored as row-major pixels.
+pub fn riemersma_pixels(
+    pixels: &mut [[u8; 3]],
+    width: u32,
+    height: u32,
+    palette: &Palette,
+    space: ColourSpace,
+    options: &RiemersmaOptions,
+) {
+    let source: Vec<[f32; 3]> = pixels.iter().map(|p| p.map(f32::from)).collect();
+    let indices = riemersma_indices(&source, width, height, palette, space, options);
+    for (pixel, idx) in pixels.iter_mut().zip(indices) {
+        *pixel = palette.get_output_colours()[idx].0;
+    }
+}
+
+/// Dithers row-major sRGB pixels, returning the palette index of each.
+#[allow(clippy::cast_precision_loss)]
+fn riemersma_indices(
+    source: &[[f32; 3]],
+    width: u32,
+    height: u32,
+    palette: &Palette,
+    space: ColourSpace,
+    options: &RiemersmaOptions,
+) -> Vec<usize> {
+    let error_space = options.error_space;
+    let colours = error_space.palette_coordinates(palette);
+
+    // newest first
+    let history = options.history.max(1);
+    let ratio = options.ratio.clamp(0.0, 1.0);
+    let weights: Vec<f32> = (0..history)
+        .map(|i| powf(ratio, i as f32 / (history - 1).max(1) as f32))
+        .collect();
+    let mut errors: VecDeque<[f32; 3]> = VecDeque::from(vec![[0.0; 3]; history]);
+    let mut indices = vec![0; source.len()];
+
+    for (x, y) in gilbert_curve(width, height) {
+        let i = y as usize * width as usize + x as usize;
+        let source = error_space.to_space(source[i]);
+        let mut value = source;
+        for (err, &weight) in errors.iter().zip(&weights) {
+            for (v, e) in value.iter_mut().zip(err) {
+                *v += e * weight;
+            }
+        }
+        let value = error_space.clamp(value);
+
+        let idx = palette.closest_idx_f32(space, error_space.to_srgb(value));
+        indices[i] = idx;
+
+        errors.pop_back();
+        errors.push_front(core::array::from_fn(|c| source[c] - colours[idx][c]));
+    }
+    indices
+}
+
+/// Returns every position of a `width` by `height` image along a generalised Hilbert curve,
+/// which works for any size rather than only powers of two.
+///
+/// <https://github.com/jakubcerveny/gilbert>
+#[allow(clippy::cast_possible_wrap)]
+#[allow(clippy::cast_sign_loss)]
+pub(crate) fn gilbert_curve(width: u32, height: u32) -> Vec<(u32, u32)> {
+    let mut points = Vec::with_capacity(width as usize * height as usize);
+    if width == 0 || height == 0 {
+        return points;
+    }
+    let mut push = |x: i64, y: i64| points.push((x as u32, y as u32));
+    let (w, h) = (i64::from(width), i64::from(height));
+    if width >= height {
+        gilbert(&mut push, (0, 0), (w, 0), (0, h));
+    } else {
+        gilbert(&mut push, (0, 0), (0, h), (w, 0));
+    }
+    points
+}
+
+/// Fills the rectangle at `(x, y)` spanned by the major axis `a` and minor axis `b`.
+fn gilbert<F: FnMut(i64, i64)>(
+    push: &mut F,
+    (x, y): (i64, i64),
+    (ax, ay): (i64, i64),
+    (bx, by): (i64, i64),
+) {
+    let (w, h) = ((ax + ay).abs(), (bx + by).abs());
+    let (dax, day) = (ax.signum(), ay.signum());
+    let (dbx, dby) = (bx.signum(), by.signum());
+
+    if h == 1 {
+        for i in 0..w {
+            push(x + i * dax, y + i * day);
+        }
+        return;
+    }
+    if w == 1 {
+        for i in 0..h {
+            push(x + i * dbx, y + i * dby);
+        }
+        return;
+    }
+
+    let (mut ax2, mut ay2) = (ax.div_euclid(2), ay.div_euclid(2));
+    let (mut bx2, mut by2) = (bx.div_euclid(2), by.div_euclid(2));
+    let (w2, h2) = ((ax2 + ay2).abs(), (bx2 + by2).abs());
+
+    if 2 * w > 3 * h {
+        // long case: split in two along the major axis
+        if w2 % 2 == 1 && w > 2 {
+            (ax2, ay2) = (ax2 + dax, ay2 + day);
+        }
+        gilbert(push, (x, y), (ax2, ay2), (bx, by));
+        gilbert(push, (x + ax2, y + ay2), (ax - ax2, ay - ay2), (bx, by));
+    } else {
+        // standard case: one step up, one long horizontal, one step down
+        if h2 % 2 == 1 && h > 2 {
+            (bx2, by2) = (bx2 + dbx, by2 + dby);
+        }
+        gilbert(push, (x, y), (bx2, by2), (ax2, ay2));
+        gilbert(push, (x + bx2, y + by2), (ax, ay), (bx - bx2, by - by2));
+        gilbert(
+            push,
+            (x + (ax - dax) + (bx2 - dbx), y + (ay - day) + (by2 - dby)),
+            (-bx2, -by2),
+            (-(ax - ax2), -(ay - ay2)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve_covers_image() {
+        for (width, height) in [(1, 1), (8, 8), (13, 7), (5, 30), (100, 3)] {
+            let curve = gilbert_curve(width, height);
+            let mut seen = vec![false; (width * height) as usize];
+            for &(x, y) in &curve {
+                assert!(x < width && y < height, "{width}x{height}: ({x}, {y})");
+                seen[(y * width + x) as usize] = true;
+            }
+            assert_eq!(curve.len(), seen.len(), "{width}x{height}");
+            assert!(seen.iter().all(|&s| s), "{width}x{height}");
+        }
+    }
+
+    #[test]
+    fn dithers_mid_grey() {
+        let mut pixels = vec![[64; 3]; 30 * 20];
+        riemersma_pixels(
+            &mut pixels,
+            30,
+            20,
+            &Palette::monochrome(),
+            ColourSpace::RGB,
+            &RiemersmaOptions::default(),
+        );
+        let white = pixels.iter().filter(|p| p[0] == 255).count();
+        assert!((130..=170).contains(&white), "{white}");
+
+        // with only the last error kept, a quarter grey alternates along the curve
+        let mut pixels = vec![[64; 3]; 30 * 20];
+        let options = RiemersmaOptions {
+            history: 1,
+            ratio: 1.0,
+            ..RiemersmaOptions::default()
+        };
+        let palette = Palette::monochrome();
+        riemersma_pixels(&mut pixels, 30, 20, &palette, ColourSpace::RGB, &options);
+        for (i, (x, y)) in gilbert_curve(30, 20).into_iter().enumerate() {
+            let expected = if i % 2 == 1 { 255 } else { 0 };
+            assert_eq!(pixels[(y * 30 + x) as usize], [expected; 3], "{i}");
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! A small seeded random number generator, so noise is reproducible across platforms and
+//! releases.
+
+/// <https://prng.di.unimi.it/splitmix64.c>
+#[derive(Clone, Debug)]
+pub(crate) struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub(crate) const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value uniformly distributed in [0, 1).
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        // the top 24 bits fit exactly in an f32's mantissa
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Returns a value uniformly distributed in [0, 1) that depends only on the seed and a position,
+/// so noise doesn't depend on the order pixels are visited in.
+pub(crate) fn position_noise(seed: u64, x: u32, y: u32) -> f32 {
+    let position = u64::from(y) << 32 | u64::from(x);
+    SplitMix64::new(seed ^ position.wrapping_mul(0xd1b5_4a32_d192_ed03)).next_f32()
+}
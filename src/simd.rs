@@ -0,0 +1,237 @@
+//! Nearest colour search with the palette stored as one array per channel, so several palette
+//! colours are compared against a pixel at once.
+//!
+//! On x86-64 the widest instructions available at runtime are used, otherwise a portable loop
+//! over fixed size chunks. Without the `std` feature the CPU can't be queried, so only the
+//! instructions enabled at compile time are used. Every path returns exactly the same result.
+
+use alloc::vec::Vec;
+
+/// Channels are padded to a multiple of this, the widest vector used.
+const LANES: usize = 16;
+/// Far enough from any colour that padding never wins.
+const PADDING: f32 = 1e30;
+
+/// Palette colours as coordinates in the space distances are measured in.
+#[derive(Clone, Debug)]
+pub(crate) struct Channels {
+    len: usize,
+    /// Index of the first colour with finite coordinates, returned when no colour is closer than
+    /// `f32::MAX`.
+    fallback: usize,
+    x: Vec<f32>,
+    y: Vec<f32>,
+    z: Vec<f32>,
+}
+
+impl Channels {
+    pub(crate) fn new(colours: &[[f32; 3]]) -> Self {
+        let padded = colours.len().div_ceil(LANES) * LANES;
+        let channel = |c: usize| {
+            let mut values: Vec<f32> = colours.iter().map(|colour| colour[c]).collect();
+            values.resize(padded, PADDING);
+            values
+        };
+        Self {
+            len: colours.len(),
+            fallback: colours
+                .iter()
+                .position(|c| c.iter().all(|v| v.is_finite()))
+                .unwrap_or(0),
+            x: channel(0),
+            y: channel(1),
+            z: channel(2),
+        }
+    }
+
+    /// Returns the index of the first colour with the smallest squared distance to `pixel`, or 0
+    /// if there are none.
+    ///
+    /// Colours with infinite coordinates are never picked, even for a NaN pixel or one so far away
+    /// that every distance overflows.
+    pub(crate) fn nearest(&self, pixel: [f32; 3]) -> usize {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if has_avx512f() {
+                // SAFETY: the CPU supports AVX-512F
+                return unsafe { self.nearest_avx512(pixel) };
+            }
+            if has_avx() {
+                // SAFETY: the CPU supports AVX
+                return unsafe { self.nearest_avx(pixel) };
+            }
+        }
+        self.nearest_portable(pixel)
+    }
+
+    /// Updates the best `(index, distance)` from the distances of the colours from `start`.
+    fn scan(&self, start: usize, distances: &[f32], best: &mut (usize, f32)) {
+        let end = self.len.min(start + distances.len());
+        for (i, &d) in (start..end).zip(distances) {
+            if d < best.1 {
+                *best = (i, d);
+            }
+        }
+    }
+
+    fn nearest_portable(&self, [px, py, pz]: [f32; 3]) -> usize {
+        const CHUNK: usize = 8;
+        let mut best = (self.fallback, f32::MAX);
+        let chunks = self
+            .x
+            .chunks_exact(CHUNK)
+            .zip(self.y.chunks_exact(CHUNK))
+            .zip(self.z.chunks_exact(CHUNK));
+        for (start, ((x, y), z)) in (0..).step_by(CHUNK).zip(chunks) {
+            let distances: [f32; CHUNK] = core::array::from_fn(|i| {
+                let (dx, dy, dz) = (x[i] - px, y[i] - py, z[i] - pz);
+                dx * dx + dy * dy + dz * dz
+            });
+            self.scan(start, &distances, &mut best);
+        }
+        best.0
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx")]
+    fn nearest_avx(&self, [px, py, pz]: [f32; 3]) -> usize {
+        use core::arch::x86_64::{
+            _mm256_add_ps, _mm256_loadu_ps, _mm256_mul_ps, _mm256_set1_ps, _mm256_storeu_ps,
+            _mm256_sub_ps,
+        };
+
+        let (px, py, pz) = (_mm256_set1_ps(px), _mm256_set1_ps(py), _mm256_set1_ps(pz));
+        let mut best = (self.fallback, f32::MAX);
+        let mut distances = [0.0; 8];
+        for start in (0..self.x.len()).step_by(8) {
+            // SAFETY: channels are padded to a multiple of 16, so 8 values from `start` are in
+            // bounds, and `distances` holds 8 values
+            unsafe {
+                let dx = _mm256_sub_ps(_mm256_loadu_ps(self.x.as_ptr().add(start)), px);
+                let dy = _mm256_sub_ps(_mm256_loadu_ps(self.y.as_ptr().add(start)), py);
+                let dz = _mm256_sub_ps(_mm256_loadu_ps(self.z.as_ptr().add(start)), pz);
+                let d = _mm256_add_ps(
+                    _mm256_add_ps(_mm256_mul_ps(dx, dx), _mm256_mul_ps(dy, dy)),
+                    _mm256_mul_ps(dz, dz),
+                );
+                _mm256_storeu_ps(distances.as_mut_ptr(), d);
+            }
+            self.scan(start, &distances, &mut best);
+        }
+        best.0
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx512f")]
+    fn nearest_avx512(&self, [px, py, pz]: [f32; 3]) -> usize {
+        use core::arch::x86_64::{
+            _mm512_add_ps, _mm512_loadu_ps, _mm512_mul_ps, _mm512_set1_ps, _mm512_storeu_ps,
+            _mm512_sub_ps,
+        };
+
+        let (px, py, pz) = (_mm512_set1_ps(px), _mm512_set1_ps(py), _mm512_set1_ps(pz));
+        let mut best = (self.fallback, f32::MAX);
+        let mut distances = [0.0; 16];
+        for start in (0..self.x.len()).step_by(16) {
+            // SAFETY: channels are padded to a multiple of 16, so 16 values from `start` are in
+            // bounds, and `distances` holds 16 values
+            unsafe {
+                let dx = _mm512_sub_ps(_mm512_loadu_ps(self.x.as_ptr().add(start)), px);
+                let dy = _mm512_sub_ps(_mm512_loadu_ps(self.y.as_ptr().add(start)), py);
+                let dz = _mm512_sub_ps(_mm512_loadu_ps(self.z.as_ptr().add(start)), pz);
+                let d = _mm512_add_ps(
+                    _mm512_add_ps(_mm512_mul_ps(dx, dx), _mm512_mul_ps(dy, dy)),
+                    _mm512_mul_ps(dz, dz),
+                );
+                _mm512_storeu_ps(distances.as_mut_ptr(), d);
+            }
+            self.scan(start, &distances, &mut best);
+        }
+        best.0
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+fn has_avx512f() -> bool {
+    is_x86_feature_detected!("avx512f")
+}
+
+#[cfg(all(target_arch = "x86_64", not(feature = "std")))]
+fn has_avx512f() -> bool {
+    cfg!(target_feature = "avx512f")
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "std"))]
+fn has_avx() -> bool {
+    is_x86_feature_detected!("avx")
+}
+
+#[cfg(all(target_arch = "x86_64", not(feature = "std")))]
+fn has_avx() -> bool {
+    cfg!(target_feature = "avx")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::SplitMix64;
+    use alloc::vec::Vec;
+
+    fn nearest_scalar(colours: &[[f32; 3]], pixel: [f32; 3]) -> usize {
+        let mut best = (0, f32::MAX);
+        for (i, c) in colours.iter().enumerate() {
+            let d: f32 = c.iter().zip(pixel).map(|(a, b)| (a - b) * (a - b)).sum();
+            if d < best.1 {
+                best = (i, d);
+            }
+        }
+        best.0
+    }
+
+    #[test]
+    fn every_path_matches_scalar() {
+        let mut rng = SplitMix64::new(5);
+        let mut random = || core::array::from_fn(|_| rng.next_f32() * 300.0 - 20.0);
+        for len in [0, 1, 7, 8, 9, 16, 17, 40] {
+            let mut colours: Vec<[f32; 3]> = (0..len).map(|_| random()).collect();
+            // duplicates must resolve to the first
+            if len > 3 {
+                colours[3] = colours[1];
+            }
+            let channels = Channels::new(&colours);
+            for _ in 0..200 {
+                let pixel = random();
+                let expected = nearest_scalar(&colours, pixel);
+                assert_eq!(channels.nearest_portable(pixel), expected);
+                assert_eq!(channels.nearest(pixel), expected);
+                #[cfg(target_arch = "x86_64")]
+                {
+                    if has_avx() {
+                        assert_eq!(unsafe { channels.nearest_avx(pixel) }, expected);
+                    }
+                    if has_avx512f() {
+                        assert_eq!(unsafe { channels.nearest_avx512(pixel) }, expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skips_infinite_colours() {
+        let channels = Channels::new(&[[f32::INFINITY; 3], [0.0; 3], [255.0; 3]]);
+        for pixel in [[f32::NAN; 3], [f32::MAX; 3], [-f32::MAX, 0.0, 0.0]] {
+            assert_eq!(channels.nearest_portable(pixel), 1);
+            assert_eq!(channels.nearest(pixel), 1);
+            #[cfg(target_arch = "x86_64")]
+            {
+                if has_avx() {
+                    assert_eq!(unsafe { channels.nearest_avx(pixel) }, 1);
+                }
+                if has_avx512f() {
+                    assert_eq!(unsafe { channels.nearest_avx512(pixel) }, 1);
+                }
+            }
+        }
+    }
+}
@@ -1,8 +1,5 @@
-use image::Rgb;
-
-pub(crate) trait EuclideanDistance {
-    fn distance_sq(&self, c1: Rgb<u8>, c2: Rgb<u8>) -> f32;
-}
+use crate::Rgb;
+use crate::float::{cbrt, powf, powi};
 
 /// Type for describing difference colour spaces.
 ///
@@ -16,44 +13,60 @@ pub enum ColourSpace {
     CIELAB,
 }
 
-impl EuclideanDistance for ColourSpace {
-    fn distance_sq(&self, c1: Rgb<u8>, c2: Rgb<u8>) -> f32 {
+impl ColourSpace {
+    /// Converts sRGB with channels nominally in [0, 255], which may lie outside that range, to
+    /// coordinates in this space. Distance is the Euclidean distance between coordinates.
+    #[must_use]
+    pub fn coordinates(self, c: [f32; 3]) -> [f32; 3] {
         match self {
-            ColourSpace::RGB => {
-                c1.0.iter()
-                    .zip(c2.0)
-                    .map(|(&c1_i, c2_i)| (f32::from(c1_i) - f32::from(c2_i)).powi(2))
-                    .sum()
-            }
-            ColourSpace::CIELAB => {
-                let cielab1 = xyz_to_cielab(rgb_to_xyz(c1.0));
-                let cielab2 = xyz_to_cielab(rgb_to_xyz(c2.0));
-                cielab1
-                    .iter()
-                    .zip(cielab2)
-                    .map(|(&c1_i, c2_i)| (c1_i - c2_i).powi(2))
-                    .sum()
-            }
+            ColourSpace::RGB => c,
+            ColourSpace::CIELAB => srgb_to_cielab(c),
         }
     }
 }
 
-// from http://www.easyrgb.com/en/math.php#text2
-fn rgb_to_xyz(input: [u8; 3]) -> [f32; 3] {
-    fn f(v: f32) -> f32 {
-        if v > 0.04045 {
-            ((v + 0.055) / 1.055).powf(2.4)
-        } else {
-            v / 12.92
-        }
+/// Rec. 601 luma of a colour.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn luma(colour: Rgb<u8>) -> u8 {
+    let [r, g, b] = colour.0.map(u32::from);
+    ((r * 299 + g * 587 + b * 114 + 500) / 1000) as u8
+}
+
+/// Converts a gamma encoded sRGB value in [0, 1] to linear light.
+#[must_use]
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v > 0.04045 {
+        powf((v + 0.055) / 1.055, 2.4)
+    } else {
+        v / 12.92
     }
-    let r = f32::from(input[0]) / 255.0;
-    let g = f32::from(input[1]) / 255.0;
-    let b = f32::from(input[2]) / 255.0;
+}
+
+/// Converts a linear light value in [0, 1] to gamma encoded sRGB.
+#[must_use]
+pub fn linear_to_srgb(v: f32) -> f32 {
+    if v > 0.003_130_8 {
+        1.055 * powf(v, 1.0 / 2.4) - 0.055
+    } else {
+        v * 12.92
+    }
+}
 
-    let r = f(r) * 100.0;
-    let g = f(g) * 100.0;
-    let b = f(b) * 100.0;
+#[cfg(test)]
+fn rgb_to_xyz(input: [u8; 3]) -> [f32; 3] {
+    rgb_to_xyz_f32(input.map(f32::from))
+}
+
+// from http://www.easyrgb.com/en/math.php#text2
+fn rgb_to_xyz_f32(input: [f32; 3]) -> [f32; 3] {
+    let r = input[0] / 255.0;
+    let g = input[1] / 255.0;
+    let b = input[2] / 255.0;
+
+    let r = srgb_to_linear(r) * 100.0;
+    let g = srgb_to_linear(g) * 100.0;
+    let b = srgb_to_linear(b) * 100.0;
 
     [
         r * 0.4124 + g * 0.3576 + b * 0.1805,
@@ -62,6 +75,19 @@ fn rgb_to_xyz(input: [u8; 3]) -> [f32; 3] {
     ]
 }
 
+fn xyz_to_rgb_f32(input: [f32; 3]) -> [f32; 3] {
+    let x = input[0] / 100.0;
+    let y = input[1] / 100.0;
+    let z = input[2] / 100.0;
+
+    [
+        x * 3.2406 - y * 1.5372 - z * 0.4986,
+        -x * 0.9689 + y * 1.8758 + z * 0.0415,
+        x * 0.0557 - y * 0.2040 + z * 1.0570,
+    ]
+    .map(|v| linear_to_srgb(v) * 255.0)
+}
+
 // From https://en.wikipedia.org/wiki/CIELAB_color_space#Converting_between_CIELAB_and_CIE_XYZ_coordinates
 const REF_X: f32 = 95.0489;
 const REF_Y: f32 = 100.0;
@@ -70,7 +96,7 @@ const REF_Z: f32 = 108.884;
 fn xyz_to_cielab(input: [f32; 3]) -> [f32; 3] {
     fn f(v: f32) -> f32 {
         if v > 0.008_856 {
-            v.powf(1.0 / 3.0)
+            powf(v, 1.0 / 3.0)
         } else {
             (7.787 * v) + (16.0 / 116.0)
         }
@@ -83,6 +109,68 @@ fn xyz_to_cielab(input: [f32; 3]) -> [f32; 3] {
     [(116.0 * y) - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
 }
 
+fn cielab_to_xyz(input: [f32; 3]) -> [f32; 3] {
+    fn f_inv(v: f32) -> f32 {
+        if powi(v, 3) > 0.008_856 {
+            powi(v, 3)
+        } else {
+            (v - 16.0 / 116.0) / 7.787
+        }
+    }
+
+    let y = (input[0] + 16.0) / 116.0;
+    let x = y + input[1] / 500.0;
+    let z = y - input[2] / 200.0;
+
+    [REF_X * f_inv(x), REF_Y * f_inv(y), REF_Z * f_inv(z)]
+}
+
+/// Converts sRGB with channels in [0, 255] to CIELAB.
+#[must_use]
+pub fn srgb_to_cielab(input: [f32; 3]) -> [f32; 3] {
+    xyz_to_cielab(rgb_to_xyz_f32(input))
+}
+
+/// Converts CIELAB to sRGB with channels in [0, 255].
+#[must_use]
+pub fn cielab_to_srgb(input: [f32; 3]) -> [f32; 3] {
+    xyz_to_rgb_f32(cielab_to_xyz(input))
+}
+
+// From https://bottosson.github.io/posts/oklab/
+/// Converts sRGB with channels in [0, 255] to OKLab.
+#[must_use]
+pub fn srgb_to_oklab(input: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = input.map(|v| srgb_to_linear(v / 255.0));
+
+    let l = cbrt(0.412_221_47 * r + 0.536_332_55 * g + 0.051_445_995 * b);
+    let m = cbrt(0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b);
+    let s = cbrt(0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b);
+
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+/// Converts OKLab to sRGB with channels in [0, 255].
+#[must_use]
+pub fn oklab_to_srgb(input: [f32; 3]) -> [f32; 3] {
+    let [l, a, b] = input;
+
+    let l_ = powi(l + 0.396_337_78 * a + 0.215_803_76 * b, 3);
+    let m_ = powi(l - 0.105_561_346 * a - 0.063_854_17 * b, 3);
+    let s_ = powi(l - 0.089_484_18 * a - 1.291_485_5 * b, 3);
+
+    [
+        4.076_741_7 * l_ - 3.307_711_6 * m_ + 0.230_969_94 * s_,
+        -1.268_438 * l_ + 2.609_757_4 * m_ - 0.341_319_38 * s_,
+        -0.004_196_086_3 * l_ - 0.703_418_6 * m_ + 1.707_614_7 * s_,
+    ]
+    .map(|v| linear_to_srgb(v) * 255.0)
+}
+
 mod tests {
     #[allow(unused_imports)] // clippy can't read macros?
     use super::*;
@@ -123,6 +211,28 @@ mod tests {
         rgb_to_xyz_3: ([12, 143, 208], [21.355, 24.274, 63.222]),
     }
 
+    macro_rules! round_trip_tests {
+        ($($name:ident: $value:expr,)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let (to, from) = $value;
+                for input in [[0.0, 0.0, 0.0], [255.0, 255.0, 255.0], [12.0, 143.0, 208.0], [250.0, 30.0, 90.0]] {
+                    let res = from(to(input));
+                    assert!((res[0] - input[0]).abs() < 0.1, "{res:?} {input:?}");
+                    assert!((res[1] - input[1]).abs() < 0.1, "{res:?} {input:?}");
+                    assert!((res[2] - input[2]).abs() < 0.1, "{res:?} {input:?}");
+                }
+            }
+        )*
+        }
+    }
+
+    round_trip_tests! {
+        cielab_round_trip: (srgb_to_cielab, cielab_to_srgb),
+        oklab_round_trip: (srgb_to_oklab, oklab_to_srgb),
+    }
+
     xyz_to_cielab_tests! {
         xyz_to_cielab_1: ([0.0,0.0,0.0],[0.0,0.0,0.0]),
         xyz_to_cielab_2: ([95.047, 100.000, 108.883], [100.0, 0.0, 0.0]),
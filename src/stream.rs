@@ -0,0 +1,234 @@
+//! Dithering an image a row at a time, for images too large to hold in memory.
+//!
+//! Only the rows that error diffusion can still reach are kept, which is two rows for
+//! Floyd–Steinberg, and the output is the same as [`crate::diffusion::diffuse`].
+
+use crate::diffusion::{Boundary, DiffusionOptions, Pass};
+use crate::{ColourSpace, Palette};
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use anyhow::Context;
+use anyhow::{Result, anyhow};
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+/// Dithers rows as they're pushed, returning each row's palette indices once it's final.
+///
+/// # Examples
+/// ```
+/// use dithering::Palette;
+/// use dithering::diffusion::DiffusionOptions;
+/// use dithering::space::ColourSpace;
+/// use dithering::stream::RowDitherer;
+///
+/// let palette = Palette::monochrome();
+/// let mut ditherer =
+///     RowDitherer::new(&palette, ColourSpace::RGB, 4, 3, &DiffusionOptions::default()).unwrap();
+/// let mut rows = Vec::new();
+/// for _ in 0..3 {
+///     ditherer.push_row(&[128; 12]).unwrap();
+///     while let Some(row) = ditherer.next_row() {
+///         rows.push(row);
+///     }
+/// }
+/// assert_eq!(rows.len(), 3);
+/// ```
+pub struct RowDitherer<'a> {
+    palette: &'a Palette,
+    space: ColourSpace,
+    options: DiffusionOptions,
+    width: u32,
+    height: u32,
+    /// Palette colours in the error space.
+    colours: Vec<[f32; 3]>,
+    pass: Pass,
+    /// Rows pushed but not yet dithered, with the source intensity of each pixel.
+    window: VecDeque<(Vec<[f32; 3]>, Vec<u8>)>,
+    pushed: u32,
+    done: u32,
+}
+
+impl<'a> RowDitherer<'a> {
+    /// Creates a ditherer for an image of the given size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the palette is empty or has more than 256 colours, or the boundary is
+    /// [`Boundary::Wrap`], which needs the whole image.
+    pub fn new(
+        palette: &'a Palette,
+        space: ColourSpace,
+        width: u32,
+        height: u32,
+        options: &DiffusionOptions,
+    ) -> Result<Self> {
+        if !(1..=256).contains(&palette.get_colours().len()) {
+            return Err(anyhow!("Palette should have 1 to 256 colours"));
+        }
+        if options.boundary == Boundary::Wrap {
+            return Err(anyhow!("Wrapped boundaries can't be streamed"));
+        }
+
+        let colours = options.error_space.palette_coordinates(palette);
+        Ok(Self {
+            palette,
+            space,
+            options: *options,
+            width,
+            height,
+            colours,
+            pass: Pass::new(*options, width, height),
+            window: VecDeque::with_capacity(options.rows_below() + 1),
+            pushed: 0,
+            done: 0,
+        })
+    }
+
+    /// Adds the next row of the source image, as `width` pixels of 3 bytes each.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the row is the wrong length or every row has already been pushed.
+    pub fn push_row(&mut self, row: &[u8]) -> Result<()> {
+        if row.len() != self.width as usize * 3 {
+            return Err(anyhow!(
+                "Rows should be {} bytes, got: {}",
+                self.width as usize * 3,
+                row.len()
+            ));
+        }
+        if self.pushed == self.height {
+            return Err(anyhow!("All {} rows have been pushed", self.height));
+        }
+
+        let error_space = self.options.error_space;
+        let work: Vec<[f32; 3]> = row
+            .chunks_exact(3)
+            .map(|p| error_space.to_space([p[0], p[1], p[2]].map(f32::from)))
+            .collect();
+        let levels = if self.options.variable.is_some() {
+            work.iter().map(|&c| error_space.level(c)).collect()
+        } else {
+            Vec::new()
+        };
+        self.window.push_back((work, levels));
+        self.pushed += 1;
+        Ok(())
+    }
+
+    /// Dithers the next row, returning the palette index of each pixel, once every row its error
+    /// reaches has been pushed.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn next_row(&mut self) -> Option<Vec<u8>> {
+        let ready = (self.done as usize + self.options.rows_below() + 1).min(self.height as usize);
+        if self.done == self.height || (self.pushed as usize) < ready {
+            return None;
+        }
+
+        let (mut current, levels) = self.window.pop_front()?;
+        let mut window: Vec<&mut [[f32; 3]]> = core::iter::once(current.as_mut_slice())
+            .chain(self.window.iter_mut().map(|(row, _)| row.as_mut_slice()))
+            .collect();
+        let mut indices = vec![0; self.width as usize];
+        let (palette, space, colours) = (self.palette, self.space, &self.colours);
+        let error_space = self.options.error_space;
+        self.pass
+            .row(self.done, &mut window, &levels, &[], &mut |x, _, pixel| {
+                let idx = palette.closest_idx_f32(space, error_space.to_srgb(pixel));
+                // the palette has at most 256 colours
+                indices[x as usize] = idx as u8;
+                colours[idx]
+            });
+        self.done += 1;
+        Some(indices)
+    }
+}
+
+/// Reads a `width` by `height` image of raw RGB bytes, one row at a time, and writes the palette
+/// index of each pixel as it's dithered.
+///
+/// # Errors
+///
+/// Returns an error if reading or writing fails, or the ditherer can't be created, see
+/// [`RowDitherer::new`].
+#[cfg(feature = "std")]
+pub fn dither_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    (width, height): (u32, u32),
+    palette: &Palette,
+    space: ColourSpace,
+    options: &DiffusionOptions,
+) -> Result<()> {
+    let mut ditherer = RowDitherer::new(palette, space, width, height, options)?;
+    let mut row = vec![0; width as usize * 3];
+    for y in 0..height {
+        reader
+            .read_exact(&mut row)
+            .context(format!("Failed to read row {y}"))?;
+        ditherer.push_row(&row)?;
+        while let Some(indices) = ditherer.next_row() {
+            writer.write_all(&indices)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::diffusion::{Variable, diffuse};
+    use crate::fixtures::{four_colours, gradient};
+    use crate::image_to_indices;
+
+    #[test]
+    fn matches_whole_image() {
+        let source = gradient(23, 17);
+        let palette = four_colours();
+        let all_options = [
+            DiffusionOptions::default(),
+            DiffusionOptions {
+                boundary: Boundary::Drop,
+                ..DiffusionOptions::default()
+            },
+            DiffusionOptions {
+                variable: Some(Variable::ZhouFang { seed: 4 }),
+                ..DiffusionOptions::default()
+            },
+        ];
+        for options in all_options {
+            let mut whole = source.clone();
+            diffuse(&mut whole, &palette, ColourSpace::CIELAB, &options);
+
+            let mut streamed = Vec::new();
+            dither_stream(
+                source.as_raw().as_slice(),
+                &mut streamed,
+                source.dimensions(),
+                &palette,
+                ColourSpace::CIELAB,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(streamed, image_to_indices(&whole, &palette), "{options:?}");
+        }
+
+        // Floyd–Steinberg only holds back one row
+        let options = DiffusionOptions::default();
+        let mut ditherer =
+            RowDitherer::new(&palette, ColourSpace::CIELAB, 23, 17, &options).unwrap();
+        for (y, row) in source.as_raw().chunks_exact(23 * 3).enumerate() {
+            ditherer.push_row(row).unwrap();
+            let ready = core::iter::from_fn(|| ditherer.next_row()).count();
+            // the last row finishes the one above it too
+            let expected = match y {
+                0 => 0,
+                16 => 2,
+                _ => 1,
+            };
+            assert_eq!(ready, expected, "{y}");
+        }
+    }
+}